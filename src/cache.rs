@@ -0,0 +1,422 @@
+use std::collections::HashMap;
+use std::fs;
+use std::hash::Hash;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::Utc;
+use jj_lib::settings::UserSettings;
+
+use crate::config::FormatConfig;
+use crate::error::Error;
+use crate::jj::{self, CollectOptions, JjStatus};
+
+/// Caches `UserSettings` per repo root, rebuilding them only when one of the
+/// config files they were read from is added, removed or modified.
+#[derive(Default)]
+pub struct SettingsCache {
+    /// User config files to read instead of jj's usual lookup.
+    user_paths: Option<Vec<PathBuf>>,
+    entries: Mutex<HashMap<PathBuf, (Vec<(PathBuf, Option<SystemTime>)>, UserSettings)>>,
+}
+
+impl SettingsCache {
+    /// A cache that reads only repo config, so tests don't depend on the
+    /// developer's own jj config.
+    #[cfg(test)]
+    pub fn without_user_config() -> Self {
+        Self {
+            user_paths: Some(Vec::new()),
+            ..Self::default()
+        }
+    }
+
+    /// The config files settings for `repo_root` are read from, with their
+    /// modification times.
+    pub fn fingerprint(&self, repo_root: &Path) -> Vec<(PathBuf, Option<SystemTime>)> {
+        jj::config_fingerprint(repo_root, &self.user_paths())
+    }
+
+    pub fn get(&self, repo_root: &Path) -> Result<UserSettings, Error> {
+        let fingerprint = self.fingerprint(repo_root);
+        let mut entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some((cached, settings)) = entries.get(repo_root)
+            && *cached == fingerprint
+        {
+            return Ok(settings.clone());
+        }
+
+        let settings = jj::create_user_settings_from(repo_root, &self.user_paths())?;
+        entries.insert(repo_root.to_path_buf(), (fingerprint, settings.clone()));
+        Ok(settings)
+    }
+
+    fn user_paths(&self) -> Vec<PathBuf> {
+        self.user_paths
+            .clone()
+            .unwrap_or_else(jj::user_config_paths)
+    }
+}
+
+/// Caches the parsed `nu_plugin_jj.toml`, re-reading it only when its path
+/// or modification time changes.
+#[derive(Default)]
+pub struct FormatConfigCache {
+    entry: Mutex<Option<(PathBuf, Option<SystemTime>, FormatConfig)>>,
+}
+
+impl FormatConfigCache {
+    pub fn get(&self, path: &Path) -> Result<FormatConfig, Error> {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        let mut entry = self
+            .entry
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some((cached_path, cached_modified, config)) = &*entry
+            && cached_path == path
+            && *cached_modified == modified
+        {
+            return Ok(config.clone());
+        }
+
+        let config = FormatConfig::load(path)?;
+        *entry = Some((path.to_path_buf(), modified, config.clone()));
+        Ok(config)
+    }
+}
+
+/// Caches shortest unique change ID prefix lengths per repo root. Lengths are
+/// keyed by change ID and dropped once the repo is at a different operation,
+/// since new commits can make a prefix ambiguous.
+#[derive(Default)]
+pub struct PrefixCache {
+    entries: Mutex<HashMap<PathBuf, (String, HashMap<String, usize>)>>,
+}
+
+impl PrefixCache {
+    /// Returns the length cached for `change_id` at `operation_id`, calling
+    /// `compute` on a miss.
+    pub fn get_or_compute(
+        &self,
+        repo_root: &Path,
+        operation_id: &str,
+        change_id: &str,
+        compute: impl FnOnce() -> usize,
+    ) -> usize {
+        let mut entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (cached_operation, lengths) = entries.entry(repo_root.to_path_buf()).or_default();
+        if cached_operation != operation_id {
+            *cached_operation = operation_id.to_string();
+            lengths.clear();
+        }
+        *lengths.entry(change_id.to_string()).or_insert_with(compute)
+    }
+}
+
+struct CacheEntry {
+    fingerprint: String,
+    /// Config files the status was read with; `authored_by_me` and
+    /// `short_id_len` depend on them.
+    config: Vec<(PathBuf, Option<SystemTime>)>,
+    options: CollectOptions,
+    status: JjStatus,
+}
+
+/// Caches `JjStatus` per repo root, reusing it until the repo's operation
+/// heads or jj config change, or different collect options are requested.
+#[derive(Default)]
+pub struct StatusCache {
+    entries: Mutex<HashMap<PathBuf, CacheEntry>>,
+    in_flight: Arc<InFlight<(PathBuf, CollectOptions), Result<JjStatus, Error>>>,
+}
+
+impl StatusCache {
+    pub fn collect(
+        &self,
+        path: &Path,
+        options: &CollectOptions,
+        settings: &SettingsCache,
+        prefixes: &PrefixCache,
+    ) -> Result<JjStatus, Error> {
+        let Some((repo_root, fingerprint)) = jj::op_heads_fingerprint(path, &options.root_search)
+        else {
+            return jj::collect(path, options, settings, prefixes);
+        };
+
+        let config = settings.fingerprint(&repo_root);
+        if let Some(entry) = self.lock().get(&repo_root)
+            && entry.fingerprint == fingerprint
+            && entry.config == config
+            && entry.options == *options
+        {
+            let mut status = entry.status.clone();
+            if !status.degraded {
+                status.age_seconds = Utc::now()
+                    .signed_duration_since(status.authored_at)
+                    .num_seconds();
+                status.age_human = jj::human_age(status.age_seconds);
+            }
+            status.path_in_repo = jj::path_in_repo(path, &repo_root);
+            status.diagnostics.cached = true;
+            return Ok(status);
+        }
+
+        match jj::collect(path, options, settings, prefixes) {
+            Ok(status) => {
+                self.lock().insert(
+                    repo_root,
+                    CacheEntry {
+                        fingerprint,
+                        config,
+                        options: options.clone(),
+                        status: status.clone(),
+                    },
+                );
+                Ok(status)
+            }
+            Err(e) => {
+                self.lock().remove(&repo_root);
+                Err(e)
+            }
+        }
+    }
+
+    /// Like [`collect`](Self::collect), but gives up with [`Error::Timeout`]
+    /// once `timeout` elapses. The worker thread is left running, so a slow
+    /// load still fills the cache for the next prompt, and calls for the same
+    /// repo and options wait on it rather than starting another.
+    pub fn collect_within(
+        self: &Arc<Self>,
+        path: &Path,
+        options: &CollectOptions,
+        settings: &Arc<SettingsCache>,
+        prefixes: &Arc<PrefixCache>,
+        timeout: Option<Duration>,
+    ) -> Result<JjStatus, Error> {
+        let Some(timeout) = timeout else {
+            return self.collect(path, options, settings, prefixes);
+        };
+
+        let Some((repo_root, _)) = jj::op_heads_fingerprint(path, &options.root_search) else {
+            return self.collect(path, options, settings, prefixes);
+        };
+
+        let cache = Arc::clone(self);
+        let settings = Arc::clone(settings);
+        let prefixes = Arc::clone(prefixes);
+        let worker_path = path.to_path_buf();
+        let worker_options = options.clone();
+        let key = (repo_root.clone(), options.clone());
+        let result = self.in_flight.run_within(key, timeout, move || {
+            cache.collect(&worker_path, &worker_options, &settings, &prefixes)
+        });
+
+        match result {
+            Ok(Ok(mut status)) => {
+                // The worker may have been started for another path in the repo.
+                status.path_in_repo = jj::path_in_repo(path, &repo_root);
+                Ok(status)
+            }
+            Ok(Err(e)) => Err(e),
+            Err(JobError::TimedOut) => Err(Error::Timeout(timeout.as_millis())),
+            Err(JobError::Panicked) => Err(Error::Jj("collect worker panicked".to_string())),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<PathBuf, CacheEntry>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+enum JobState<T> {
+    Running,
+    Done(T),
+    Panicked,
+}
+
+struct Job<T> {
+    state: Mutex<JobState<T>>,
+    done: Condvar,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum JobError {
+    TimedOut,
+    Panicked,
+}
+
+/// Work running on detached threads, keyed so that callers asking for the
+/// same thing join the running job instead of spawning another.
+struct InFlight<K, T> {
+    jobs: Mutex<HashMap<K, Arc<Job<T>>>>,
+}
+
+impl<K, T> Default for InFlight<K, T> {
+    fn default() -> Self {
+        Self {
+            jobs: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K, T> InFlight<K, T>
+where
+    K: Eq + Hash + Clone + Send + 'static,
+    T: Clone + Send + 'static,
+{
+    /// Waits up to `timeout` for the job running under `key`, starting it
+    /// with `work` when none is.
+    fn run_within(
+        self: &Arc<Self>,
+        key: K,
+        timeout: Duration,
+        work: impl FnOnce() -> T + Send + 'static,
+    ) -> Result<T, JobError> {
+        let deadline = Instant::now() + timeout;
+        let job = self.join_or_spawn(key, work);
+
+        let mut state = job.state.lock().unwrap_or_else(|p| p.into_inner());
+        loop {
+            match &*state {
+                JobState::Done(result) => return Ok(result.clone()),
+                JobState::Panicked => return Err(JobError::Panicked),
+                JobState::Running => {}
+            }
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+                return Err(JobError::TimedOut);
+            };
+            state = job
+                .done
+                .wait_timeout(state, remaining)
+                .unwrap_or_else(|p| p.into_inner())
+                .0;
+        }
+    }
+
+    fn join_or_spawn(
+        self: &Arc<Self>,
+        key: K,
+        work: impl FnOnce() -> T + Send + 'static,
+    ) -> Arc<Job<T>> {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|p| p.into_inner());
+        if let Some(job) = jobs.get(&key) {
+            return Arc::clone(job);
+        }
+
+        let job = Arc::new(Job {
+            state: Mutex::new(JobState::Running),
+            done: Condvar::new(),
+        });
+        jobs.insert(key.clone(), Arc::clone(&job));
+        drop(jobs);
+
+        let in_flight = Arc::clone(self);
+        let worker_job = Arc::clone(&job);
+        thread::spawn(move || {
+            let state = match panic::catch_unwind(AssertUnwindSafe(work)) {
+                Ok(result) => JobState::Done(result),
+                Err(_) => JobState::Panicked,
+            };
+            in_flight
+                .jobs
+                .lock()
+                .unwrap_or_else(|p| p.into_inner())
+                .remove(&key);
+            *worker_job.state.lock().unwrap_or_else(|p| p.into_inner()) = state;
+            worker_job.done.notify_all();
+        });
+        job
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InFlight, JobError, PrefixCache};
+    use std::cell::Cell;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn timed_out_jobs_are_joined_not_respawned() {
+        let in_flight = Arc::new(InFlight::<&str, usize>::default());
+        let started = Arc::new(AtomicUsize::new(0));
+        let slow = |started: &Arc<AtomicUsize>| {
+            let started = Arc::clone(started);
+            move || {
+                started.fetch_add(1, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(200));
+                7
+            }
+        };
+
+        let short = Duration::from_millis(10);
+        assert_eq!(
+            in_flight.run_within("repo", short, slow(&started)),
+            Err(JobError::TimedOut)
+        );
+        assert_eq!(
+            in_flight.run_within("repo", short, slow(&started)),
+            Err(JobError::TimedOut)
+        );
+        assert_eq!(
+            in_flight.run_within("repo", Duration::from_secs(5), slow(&started)),
+            Ok(7)
+        );
+        assert_eq!(started.load(Ordering::SeqCst), 1);
+
+        // Once finished, the next call starts fresh work.
+        assert_eq!(
+            in_flight.run_within("repo", Duration::from_secs(5), slow(&started)),
+            Ok(7)
+        );
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn panicking_jobs_report_and_clear() {
+        let in_flight = Arc::new(InFlight::<&str, usize>::default());
+        let result = in_flight.run_within("repo", Duration::from_secs(5), || panic!("boom"));
+        assert_eq!(result, Err(JobError::Panicked));
+        assert_eq!(
+            in_flight.run_within("repo", Duration::from_secs(5), || 1),
+            Ok(1)
+        );
+    }
+
+    #[test]
+    fn prefix_lengths_are_reused_until_the_operation_changes() {
+        let cache = PrefixCache::default();
+        let root = Path::new("/repo");
+        let computed = Cell::new(0);
+        let compute = |len| {
+            computed.set(computed.get() + 1);
+            len
+        };
+
+        assert_eq!(cache.get_or_compute(root, "op1", "kxyz", || compute(3)), 3);
+        assert_eq!(cache.get_or_compute(root, "op1", "kxyz", || compute(5)), 3);
+        assert_eq!(computed.get(), 1);
+
+        assert_eq!(cache.get_or_compute(root, "op2", "kxyz", || compute(5)), 5);
+        assert_eq!(computed.get(), 2);
+
+        let other = Path::new("/other");
+        assert_eq!(cache.get_or_compute(other, "op2", "kxyz", || compute(4)), 4);
+        assert_eq!(cache.get_or_compute(root, "op2", "kxyz", || compute(6)), 5);
+        assert_eq!(computed.get(), 3);
+    }
+}
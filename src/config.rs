@@ -0,0 +1,180 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::error::Error;
+
+/// File name looked up in nushell's default config directory.
+pub const FORMAT_CONFIG_FILE: &str = "nu_plugin_jj.toml";
+
+/// Defaults for `jj-prompt format`, read from `nu_plugin_jj.toml`. Keys are
+/// the flag names; any flag passed on the command line wins over the file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct FormatConfig {
+    pub icon: Option<String>,
+    pub icon_conflict: Option<String>,
+    pub icon_merge: Option<String>,
+    pub icon_empty: Option<String>,
+    pub icon_color: Option<String>,
+    pub icon_bg: Option<String>,
+    pub change_id_color: Option<String>,
+    pub change_id_rest_color: Option<String>,
+    pub stale_color: Option<String>,
+    pub stale_after_secs: Option<i64>,
+    pub bookmark_color: Option<String>,
+    pub divergence_color: Option<String>,
+    pub status_color: Option<String>,
+    pub status_bg: Option<String>,
+    pub conflict: Option<String>,
+    pub divergent: Option<String>,
+    pub hidden: Option<String>,
+    pub immutable: Option<String>,
+    pub conflict_color: Option<String>,
+    pub divergent_color: Option<String>,
+    pub hidden_color: Option<String>,
+    pub immutable_color: Option<String>,
+    pub merge_symbol: Option<String>,
+    pub stale: Option<String>,
+    pub git_head_symbol: Option<String>,
+    pub signed_symbol: Option<String>,
+    pub dirty_symbol: Option<String>,
+    pub push_symbol: Option<String>,
+    pub ahead_symbol: Option<String>,
+    pub behind_symbol: Option<String>,
+    pub descendant_symbol: Option<String>,
+    pub synced_symbol: Option<String>,
+    pub unsynced_symbol: Option<String>,
+    pub show_descendants: bool,
+    pub change_id_len: Option<i64>,
+    pub empty_text: Option<String>,
+    pub no_desc_text: Option<String>,
+    pub root_text: Option<String>,
+    pub degraded_text: Option<String>,
+    pub desc_len: Option<i64>,
+    pub ellipsis: Option<String>,
+    pub template: Option<String>,
+    pub truncate_words: bool,
+    pub full_description: bool,
+    pub no_color: bool,
+    pub no_icon: bool,
+    pub no_bookmarks: bool,
+    pub no_flags: bool,
+    pub no_description: bool,
+    pub reverse: bool,
+    pub multiline: bool,
+    pub powerline: bool,
+    pub separator: Option<String>,
+    pub prefix: Option<String>,
+    pub suffix: Option<String>,
+    pub json: bool,
+    pub max_bookmarks: Option<i64>,
+    pub show_bookmark_distance: bool,
+    pub theme: Option<String>,
+    pub nerd_fonts: bool,
+    pub link_root: bool,
+}
+
+impl FormatConfig {
+    /// Reads `path`; a missing file yields the hardcoded defaults. Unknown
+    /// keys are ignored, so a file written for a newer version still loads.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(Error::Config(format!("read {}: {e}", path.display()))),
+        }
+    }
+
+    fn parse(text: &str) -> Result<Self, Error> {
+        toml::from_str(text).map_err(|e| Error::Config(e.message().to_string()))
+    }
+}
+
+/// Environment variable the platform config directory is found under.
+#[cfg(windows)]
+pub const PLATFORM_CONFIG_VAR: &str = "APPDATA";
+#[cfg(not(windows))]
+pub const PLATFORM_CONFIG_VAR: &str = "HOME";
+
+/// Where nushell's `$nu.default-config-dir` points: `$XDG_CONFIG_HOME/nushell`
+/// when set, otherwise the platform config directory under `platform_base`,
+/// the value of [`PLATFORM_CONFIG_VAR`].
+pub fn format_config_path(
+    xdg_config_home: Option<&str>,
+    platform_base: Option<&str>,
+) -> Option<PathBuf> {
+    let config_dir = match xdg_config_home.filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => platform_config_dir(Path::new(platform_base.filter(|base| !base.is_empty())?)),
+    };
+    Some(config_dir.join("nushell").join(FORMAT_CONFIG_FILE))
+}
+
+#[cfg(target_os = "macos")]
+fn platform_config_dir(home: &Path) -> PathBuf {
+    home.join("Library/Application Support")
+}
+
+#[cfg(windows)]
+fn platform_config_dir(app_data: &Path) -> PathBuf {
+    app_data.to_path_buf()
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn platform_config_dir(home: &Path) -> PathBuf {
+    home.join(".config")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FormatConfig, format_config_path};
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn keys_use_flag_names() {
+        let config = FormatConfig::parse(
+            r#"
+            icon = "@"
+            merge-symbol = "m"
+            desc-len = 40
+            nerd-fonts = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.icon.as_deref(), Some("@"));
+        assert_eq!(config.merge_symbol.as_deref(), Some("m"));
+        assert_eq!(config.desc_len, Some(40));
+        assert!(config.nerd_fonts);
+        assert!(!config.json);
+        assert_eq!(config.theme, None);
+    }
+
+    #[test]
+    fn unknown_keys_are_ignored() {
+        let config = FormatConfig::parse("desc_len = 40\nicon = \"@\"").unwrap();
+        assert_eq!(config.desc_len, None);
+        assert_eq!(config.icon.as_deref(), Some("@"));
+    }
+
+    #[test]
+    fn missing_file_uses_defaults() {
+        let path = env::temp_dir().join("nu_plugin_jj_missing_format_config.toml");
+        let _ = fs::remove_file(&path);
+        let config = FormatConfig::load(&path).unwrap();
+        assert_eq!(config.icon, None);
+    }
+
+    #[test]
+    fn path_prefers_xdg_config_home() {
+        assert_eq!(
+            format_config_path(Some("/xdg"), Some("/home/me")).as_deref(),
+            Some(Path::new("/xdg/nushell/nu_plugin_jj.toml"))
+        );
+        assert_eq!(format_config_path(None, None), None);
+    }
+}
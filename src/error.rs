@@ -1,7 +1,17 @@
 use thiserror::Error;
 
-#[derive(Debug, Error)]
+#[derive(Debug, Clone, Error)]
 pub enum Error {
+    #[error("jj: not a repository")]
+    NotARepository,
     #[error("jj: {0}")]
     Jj(String),
+    #[error("jj: timed out after {0}ms")]
+    Timeout(u128),
+    #[error("jj: no workspace named '{0}'")]
+    UnknownWorkspace(String),
+    #[error("jj: no bookmark named '{0}'")]
+    UnknownBookmark(String),
+    #[error("nu_plugin_jj.toml: {0}")]
+    Config(String),
 }
@@ -1,50 +1,444 @@
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 
-use jj_lib::backend::CommitId;
+use chrono::{DateTime, FixedOffset, Offset as _, Utc};
+use futures::StreamExt as _;
+use jj_lib::backend::{BackendError, CommitId, Timestamp, TreeValue};
+use jj_lib::commit::Commit;
 use jj_lib::config::{ConfigLayer, ConfigSource, StackedConfig};
+use jj_lib::config_resolver::{self, ConfigResolutionContext};
+use jj_lib::conflicts::{MaterializedTreeValue, materialize_tree_value};
+use jj_lib::diff::{ContentDiff, DiffHunkKind};
+use jj_lib::git::{self, REMOTE_NAME_FOR_LOCAL_GIT_REPO};
 use jj_lib::hex_util::encode_reverse_hex;
+use jj_lib::matchers::EverythingMatcher;
+use jj_lib::merged_tree::MergedTreeValue;
 use jj_lib::object_id::ObjectId;
-use jj_lib::repo::Repo;
-use jj_lib::repo::StoreFactories;
+use jj_lib::op_store::RefTarget;
+use jj_lib::op_walk;
+use jj_lib::ref_name::WorkspaceName;
+use jj_lib::repo::{ReadonlyRepo, Repo, StoreFactories};
+use jj_lib::repo_path::{RepoPath, RepoPathBuf, RepoPathUiConverter};
+use jj_lib::revset::{
+    self, ResolvedRevsetExpression, RevsetAliasesMap, RevsetDiagnostics, RevsetExpression,
+    RevsetExtensions, RevsetParseContext, RevsetWorkspaceContext, SymbolResolver,
+};
 use jj_lib::settings::UserSettings;
-use jj_lib::str_util::StringMatcher;
+use jj_lib::signing::SigStatus;
+use jj_lib::str_util::{StringMatcher, StringPattern};
 use jj_lib::workspace::{Workspace, default_working_copy_factories};
 use pollster::FutureExt as _;
 
+use crate::cache::{PrefixCache, SettingsCache};
 use crate::error::Error;
 
 type Result<T> = std::result::Result<T, Error>;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    Added,
+    Modified,
+    Deleted,
+}
+
+impl FileStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FileStatus::Added => "added",
+            FileStatus::Modified => "modified",
+            FileStatus::Deleted => "deleted",
+        }
+    }
+}
+
+/// Outcome of verifying a commit's signature.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SignatureStatus {
+    Good,
+    Bad,
+    /// Unsigned, not verified, or no way to verify.
+    #[default]
+    Unknown,
+}
+
+impl SignatureStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SignatureStatus::Good => "good",
+            SignatureStatus::Bad => "bad",
+            SignatureStatus::Unknown => "unknown",
+        }
+    }
+}
+
 #[derive(Debug)]
+pub struct FileChange {
+    pub path: String,
+    pub status: FileStatus,
+}
+
+#[derive(Debug)]
+pub struct FileDiff {
+    pub path: String,
+    pub status: FileStatus,
+    /// Unified diff text including the `---`/`+++` headers.
+    pub diff: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub path: String,
+    /// Number of sides being merged; 2 for an ordinary two-way conflict.
+    pub num_sides: usize,
+}
+
+#[derive(Debug, Clone)]
 pub struct Bookmark {
     pub name: String,
     pub distance: usize,
+    /// Full change ID of the commit the bookmark points at.
+    pub target: String,
+    /// The local bookmark tracks a remote bookmark.
+    pub is_tracked: bool,
+    /// The bookmark exists on a non-git remote.
+    pub has_remote: bool,
+    /// A remote copy points where the local bookmark does. Vacuously true
+    /// without remotes.
+    pub is_synced: bool,
+    /// The local bookmark is ahead of its remote copy.
+    pub needs_push: bool,
 }
 
-#[derive(Debug)]
+/// Timings and counters from the `collect` call that produced a status.
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    /// Time spent loading the workspace and the repo at head.
+    pub load_ms: f64,
+    /// Time spent searching ancestors for bookmarks.
+    pub ancestor_walk_ms: f64,
+    /// Commits visited by the bookmark search.
+    pub commits_visited: usize,
+    /// Wall time of the whole `collect` call.
+    pub total_ms: f64,
+    /// The status was served from the cache, so the numbers describe the
+    /// call that filled it.
+    pub cached: bool,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct JjStatus {
     pub repo_root: String,
+    /// The queried path relative to `repo_root`; empty at the root, `None`
+    /// when the path is outside the repo (e.g. with `--repository`).
+    pub path_in_repo: Option<String>,
+    pub operation_id: String,
+    pub colocated: bool,
+    /// The working-copy commit couldn't be read (corrupt or garbage
+    /// collected); every field other than the repo-level ones is empty.
+    pub degraded: bool,
     pub change_id: String,
+    pub change_id_full: String,
     pub change_id_prefix_len: usize,
+    /// Short ID length the user's jj config displays; 8 when unset.
+    pub short_id_len: usize,
+    pub commit_id: String,
+    pub commit_id_prefix_len: usize,
+    pub parents: Vec<String>,
+    pub is_merge: bool,
+    pub is_root: bool,
     pub bookmarks: Vec<Bookmark>,
+    /// First line of the description; `description_full` has all of it.
     pub description: String,
+    pub description_full: String,
+    pub author_name: String,
+    pub author_email: String,
+    /// The author email matches the configured `user.email`; false when the
+    /// jj config doesn't set one.
+    pub authored_by_me: bool,
+    pub committer_email: String,
+    pub authored_at: DateTime<FixedOffset>,
+    pub age_seconds: i64,
+    /// `age_seconds` in the largest whole unit, e.g. `3h`; see `human_age`.
+    pub age_human: String,
     pub empty: bool,
+    pub files_added: usize,
+    pub files_modified: usize,
+    pub files_deleted: usize,
+    pub lines_added: usize,
+    pub lines_removed: usize,
+    /// Regular files added relative to the parent. Narrower than
+    /// `files_added`, which also counts new symlinks, submodules and
+    /// conflicted entries.
+    pub new_files: usize,
+    /// The working-copy commit's tree differs from its parent's.
+    pub dirty: bool,
     pub conflict: bool,
+    pub conflict_count: usize,
+    pub conflicted_files: Vec<String>,
+    pub conflicts: Vec<Conflict>,
     pub divergent: bool,
+    /// Visible commits sharing the change ID; more than 1 when divergent.
+    pub divergent_count: usize,
     pub hidden: bool,
+    /// Why the commit is hidden: `obsoleted` when other visible commits now
+    /// carry its change ID, `abandoned` when none do. `None` when visible.
+    pub hidden_reason: Option<String>,
     pub immutable: bool,
+    pub is_stale: bool,
+    pub git_head_behind: bool,
+    pub signed: bool,
+    /// Only verified with `CollectOptions::verify_signatures`; `Unknown`
+    /// otherwise.
+    pub signature_status: SignatureStatus,
     pub has_remote: bool,
     pub is_synced: bool,
+    pub needs_push: bool,
+    pub has_trunk: bool,
+    /// The commit is the trunk head or one of its ancestors.
+    pub on_trunk: bool,
+    pub ahead: usize,
+    pub behind: usize,
+    /// Visible descendants of the working-copy commit, capped at
+    /// `MAX_DESCENDANTS`.
+    pub descendant_count: usize,
+    pub diagnostics: Diagnostics,
 }
 
-pub fn collect(path: &Path) -> Result<Option<JjStatus>> {
-    let repo_root = match find_repo_root(path) {
-        Some(root) => root,
-        None => return Ok(None),
-    };
+#[derive(Debug)]
+pub struct LogEntry {
+    pub change_id: String,
+    pub commit_id: String,
+    pub description: String,
+    pub author_email: String,
+    pub authored_at: DateTime<FixedOffset>,
+}
+
+#[derive(Debug)]
+pub struct BookmarkRef {
+    pub name: String,
+    pub remote: Option<String>,
+    pub target_change_id: Option<String>,
+    pub is_tracked: bool,
+}
+
+#[derive(Debug)]
+pub struct OpLogEntry {
+    pub operation_id: String,
+    pub description: String,
+    pub time_started: DateTime<FixedOffset>,
+    pub time_ended: DateTime<FixedOffset>,
+    pub user: String,
+}
+
+#[derive(Debug)]
+pub struct WorkspaceEntry {
+    pub name: String,
+    pub change_id: String,
+    pub description: String,
+    pub empty: bool,
+}
+
+/// Upper bound on `JjStatus::descendant_count`, so a working copy far
+/// behind a busy branch doesn't walk the whole graph.
+const MAX_DESCENDANTS: usize = 100;
+
+/// Upper bound on `JjStatus::ahead` and `JjStatus::behind`, so a long-lived
+/// branch doesn't turn every prompt into a walk of its whole history.
+const MAX_TRUNK_DISTANCE: usize = 100;
+
+/// How `find_repo_root` locates the workspace for a starting path.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct RootSearch {
+    /// A `.jj` directory, or the workspace containing one, to use instead of
+    /// searching from the starting path.
+    pub repository: Option<PathBuf>,
+    /// Stop ascending at the first parent on a different filesystem device.
+    pub same_filesystem: bool,
+    /// Most parent directories to visit above the starting path; unlimited
+    /// when `None`.
+    pub max_depth: Option<usize>,
+}
+
+/// Knobs that change what `collect` reports. Part of the status cache key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CollectOptions {
+    pub trunk_bookmarks: Vec<String>,
+    pub trunk_remotes: Vec<String>,
+    /// How many ancestors to search for bookmarks; 0 only considers the
+    /// working-copy commit itself.
+    pub bookmark_depth: usize,
+    /// Skip the ancestor search when the working-copy commit already has
+    /// bookmarks of its own.
+    pub nearest_bookmark_only: bool,
+    /// Remote that bookmark sync state is compared against; the first
+    /// non-git remote holding each bookmark when `None`.
+    pub remote: Option<String>,
+    pub root_search: RootSearch,
+    /// Workspace whose working-copy commit to inspect; the loaded workspace
+    /// when `None`.
+    pub workspace: Option<String>,
+    /// Revset naming a single commit to inspect instead of a working copy.
+    /// Takes precedence over `workspace`.
+    pub revision: Option<String>,
+    /// Match remote bookmarks to local ones case-insensitively.
+    pub ignore_bookmark_case: bool,
+    /// Read every changed file to fill in `lines_added`/`lines_removed`;
+    /// both stay 0 otherwise.
+    pub line_stats: bool,
+    /// Verify the working-copy commit's signature for `signature_status`,
+    /// which runs the configured gpg/ssh backend; `Unknown` otherwise.
+    pub verify_signatures: bool,
+    /// Report a described commit as non-empty even when its tree matches its
+    /// parent's.
+    pub describe_is_nonempty: bool,
+    /// Treat the synthetic `git` remote of a colocated repo like any other
+    /// remote when computing sync state and immutable heads.
+    pub include_git_remote: bool,
+    /// Skip every remote bookmark lookup: bookmarks report as synced with no
+    /// remote, there is no trunk, and only tags count as immutable heads.
+    pub no_remote: bool,
+}
+
+impl Default for CollectOptions {
+    fn default() -> Self {
+        Self {
+            trunk_bookmarks: vec!["main".into(), "master".into(), "trunk".into()],
+            trunk_remotes: vec!["origin".into(), "upstream".into()],
+            bookmark_depth: 10,
+            nearest_bookmark_only: false,
+            remote: None,
+            root_search: RootSearch::default(),
+            workspace: None,
+            revision: None,
+            ignore_bookmark_case: false,
+            line_stats: false,
+            verify_signatures: false,
+            describe_is_nonempty: false,
+            include_git_remote: false,
+            no_remote: false,
+        }
+    }
+}
+
+impl CollectOptions {
+    fn is_trunk(&self, name: &str, remote: &str) -> bool {
+        self.trunk_remotes.iter().any(|r| r == remote)
+            && self.trunk_bookmarks.iter().any(|b| b == name)
+    }
+}
+
+struct LoadedRepo {
+    root: PathBuf,
+    settings: UserSettings,
+    workspace: Workspace,
+    repo: Arc<ReadonlyRepo>,
+}
+
+impl LoadedRepo {
+    fn wc_commit_id(&self) -> Result<CommitId> {
+        self.repo
+            .view()
+            .wc_commit_ids()
+            .get(self.workspace.workspace_name())
+            .cloned()
+            .ok_or_else(|| Error::Jj("workspace has no working-copy commit".to_string()))
+    }
+
+    /// Evaluates `revset` against the loaded repo using the user's revset
+    /// aliases, stopping after `limit` commits when given.
+    fn evaluate_revset(&self, revset: &str, limit: Option<usize>) -> Result<Vec<CommitId>> {
+        let mut aliases_map = RevsetAliasesMap::new();
+        for decl in self.settings.table_keys("revset-aliases") {
+            let defn = self
+                .settings
+                .get_string(["revset-aliases", decl])
+                .map_err(|e| Error::Jj(format!("read revset alias {decl}: {e}")))?;
+            aliases_map
+                .insert(decl, defn)
+                .map_err(|e| Error::Jj(format!("parse revset alias {decl}: {e}")))?;
+        }
+
+        let extensions = RevsetExtensions::default();
+        let path_converter = RepoPathUiConverter::Fs {
+            cwd: self.root.clone(),
+            base: self.root.clone(),
+        };
+        let context = RevsetParseContext {
+            aliases_map: &aliases_map,
+            local_variables: HashMap::new(),
+            user_email: self.settings.user_email(),
+            date_pattern_context: chrono::Local::now().into(),
+            default_ignored_remote: Some(REMOTE_NAME_FOR_LOCAL_GIT_REPO),
+            use_glob_by_default: true,
+            extensions: &extensions,
+            workspace: Some(RevsetWorkspaceContext {
+                path_converter: &path_converter,
+                workspace_name: self.workspace.workspace_name(),
+            }),
+        };
 
-    let settings = create_user_settings()?;
+        let mut diagnostics = RevsetDiagnostics::new();
+        let expression = revset::parse(&mut diagnostics, revset, &context)
+            .map_err(|e| Error::Jj(format!("parse revset {revset}: {e}")))?;
+        let symbol_resolver =
+            SymbolResolver::new(self.repo.as_ref(), extensions.symbol_resolvers());
+        let resolved = expression
+            .resolve_user_expression(self.repo.as_ref(), &symbol_resolver)
+            .map_err(|e| Error::Jj(format!("resolve revset {revset}: {e}")))?;
+        let evaluated = resolved
+            .evaluate(self.repo.as_ref())
+            .map_err(|e| Error::Jj(format!("evaluate revset {revset}: {e}")))?;
+
+        evaluated
+            .iter()
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|id| id.map_err(|e| Error::Jj(format!("evaluate revset {revset}: {e}"))))
+            .collect()
+    }
+
+    /// Resolves `revset` to exactly one commit.
+    fn resolve_single_revision(&self, revset: &str) -> Result<CommitId> {
+        let mut ids = self.evaluate_revset(revset, Some(2))?;
+        match ids.len() {
+            1 => Ok(ids.remove(0)),
+            0 => Err(Error::Jj(format!(
+                "revision {revset} resolved to no commits"
+            ))),
+            _ => Err(Error::Jj(format!(
+                "revision {revset} resolved to more than one commit"
+            ))),
+        }
+    }
+
+    /// Uses the configured `immutable_heads()` alias when the user has one,
+    /// otherwise the built-in trunk/tag/untracked heuristic.
+    fn immutable_heads(&self, options: &CollectOptions) -> Result<HashSet<CommitId>> {
+        if self
+            .settings
+            .get_string(["revset-aliases", "immutable_heads()"])
+            .is_ok()
+        {
+            return Ok(self
+                .evaluate_revset("immutable_heads()", None)?
+                .into_iter()
+                .collect());
+        }
+        Ok(find_immutable_heads(self.repo.view(), options))
+    }
+}
+
+/// Loads the repo containing `path`. Fails with [`Error::NotARepository`]
+/// when no `.jj` directory is found above it, so callers can tell "nothing to
+/// show" apart from a repo that exists but couldn't be read.
+fn load_repo(path: &Path, search: &RootSearch, settings: &SettingsCache) -> Result<LoadedRepo> {
+    let repo_root = find_repo_root(path, search).ok_or(Error::NotARepository)?;
+
+    let settings = settings.get(&repo_root)?;
 
     let workspace = Workspace::load(
         &settings,
@@ -60,215 +454,2401 @@ pub fn collect(path: &Path) -> Result<Option<JjStatus>> {
         .block_on()
         .map_err(|e| Error::Jj(format!("load repo: {e}")))?;
 
-    let view = repo.view();
-    let workspace_name = workspace.workspace_name().to_owned();
+    Ok(LoadedRepo {
+        root: repo_root,
+        settings,
+        workspace,
+        repo,
+    })
+}
+
+pub fn collect(
+    path: &Path,
+    options: &CollectOptions,
+    settings: &SettingsCache,
+    prefixes: &PrefixCache,
+) -> Result<JjStatus> {
+    let started = Instant::now();
+    let loaded = load_repo(path, &options.root_search, settings)?;
+    let load_ms = elapsed_ms(started);
+
+    let colocated = loaded.root.join(".git").exists();
+
+    let current_workspace = loaded.workspace.workspace_name();
+    let is_current_workspace = options
+        .workspace
+        .as_deref()
+        .is_none_or(|name| name == current_workspace.as_str());
 
-    let wc_id = match view.wc_commit_ids().get(&workspace_name).cloned() {
-        Some(id) => id,
-        None => return Ok(None),
+    let wc_id = match (&options.revision, &options.workspace) {
+        (Some(revision), _) => loaded.resolve_single_revision(revision)?,
+        (None, Some(name)) if !is_current_workspace => loaded
+            .repo
+            .view()
+            .wc_commit_ids()
+            .get(WorkspaceName::new(name))
+            .cloned()
+            .ok_or_else(|| Error::UnknownWorkspace(name.clone()))?,
+        _ => loaded.wc_commit_id()?,
     };
 
-    let commit = repo
-        .store()
-        .get_commit(&wc_id)
-        .map_err(|e| Error::Jj(format!("get commit: {e}")))?;
+    let repo = &loaded.repo;
+    let view = repo.view();
+
+    let commit = match repo.store().get_commit(&wc_id) {
+        Ok(commit) => commit,
+        Err(BackendError::ObjectNotFound { .. }) => {
+            let mut status = degraded_status(&loaded, path, colocated);
+            status.diagnostics.load_ms = load_ms;
+            status.diagnostics.total_ms = elapsed_ms(started);
+            return Ok(status);
+        }
+        Err(e) => return Err(Error::Jj(format!("get commit: {e}"))),
+    };
 
     let change_id_full = encode_reverse_hex(commit.change_id().as_bytes());
-    let change_id_prefix_len = repo
-        .shortest_unique_change_id_prefix_len(commit.change_id())
-        .unwrap_or(8)
+    let change_id_prefix_len = prefixes
+        .get_or_compute(&loaded.root, &repo.op_id().hex(), &change_id_full, || {
+            repo.shortest_unique_change_id_prefix_len(commit.change_id())
+                .unwrap_or(8)
+        })
         .min(change_id_full.len());
-    let change_id = change_id_full[..8.min(change_id_full.len())].to_string();
+    let change_id = short_change_id(&change_id_full);
+    let short_id_len = configured_short_id_len(&loaded.settings).unwrap_or(8);
 
-    let empty = commit
-        .is_empty(repo.as_ref())
-        .block_on()
-        .map_err(|e| Error::Jj(format!("check empty: {e}")))?;
+    let commit_id = commit.id().hex();
+    let commit_id_prefix_len = repo
+        .index()
+        .shortest_unique_commit_id_prefix_len(commit.id())
+        .unwrap_or(8)
+        .min(commit_id.len());
+
+    // The virtual root commit is kept as a parent (`zzzzzzzz`) rather than
+    // filtered, matching what `jj log` shows.
+    let parents = commit
+        .parents()
+        .map(|parent| {
+            parent
+                .map(|parent| short_change_id(&encode_reverse_hex(parent.change_id().as_bytes())))
+                .map_err(|e| Error::Jj(format!("get parent: {e}")))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let is_merge = commit.parent_ids().len() > 1;
+    let is_root = commit.parent_ids() == [repo.store().root_commit_id().clone()];
+
+    let unchanged = is_empty_commit(repo, &commit)?;
+    let empty = reports_empty(
+        unchanged,
+        commit.description(),
+        options.describe_is_nonempty,
+    );
+
+    let (mut files_added, mut files_modified, mut files_deleted) = (0, 0, 0);
+    let (mut lines_added, mut lines_removed) = (0, 0);
+    let mut new_files = 0;
+    if !unchanged {
+        for change in diff_against_parent(repo, &commit)? {
+            match change.status {
+                FileStatus::Added => {
+                    files_added += 1;
+                    if matches!(
+                        change.after.as_resolved(),
+                        Some(Some(TreeValue::File { .. }))
+                    ) {
+                        new_files += 1;
+                    }
+                }
+                FileStatus::Modified => files_modified += 1,
+                FileStatus::Deleted => files_deleted += 1,
+            }
+
+            if !options.line_stats {
+                continue;
+            }
+            // A file that can't be read only drops out of the line counts.
+            let before = read_text_file(repo, &change.path, change.before);
+            let after = read_text_file(repo, &change.path, change.after);
+            if let (Ok(Some(before)), Ok(Some(after))) = (before, after) {
+                let (added, removed) = count_line_changes(&before, &after);
+                lines_added += added;
+                lines_removed += removed;
+            }
+        }
+    }
+    let dirty = !unchanged;
 
     let conflict = commit.has_conflict();
+    let conflicts = if conflict {
+        tree_conflicts(&commit)
+    } else {
+        Vec::new()
+    };
+    let conflicted_files: Vec<String> = conflicts.iter().map(|c| c.path.clone()).collect();
+    let conflict_count = conflicts.len();
 
-    let divergent = match repo.resolve_change_id(commit.change_id()) {
-        Ok(Some(resolved)) => resolved.is_divergent(),
-        Ok(None) | Err(_) => false,
+    let divergent_count = match repo.resolve_change_id(commit.change_id()) {
+        Ok(Some(resolved)) => resolved.visible_with_offsets().count(),
+        Ok(None) | Err(_) => 0,
     };
+    let divergent = divergent_count > 1;
 
     let hidden = commit.is_hidden(repo.as_ref()).unwrap_or(false);
+    // `divergent_count` only counts visible commits, so a hidden commit whose
+    // change lives on was rewritten rather than abandoned.
+    let hidden_reason = hidden.then(|| {
+        if divergent_count > 0 {
+            "obsoleted".to_string()
+        } else {
+            "abandoned".to_string()
+        }
+    });
 
-    let immutable_heads = find_immutable_heads(view);
+    // Mirrors jj's stale check: the working copy was last updated at another
+    // operation and its checked-out tree no longer matches the @ commit. Only
+    // the loaded workspace's on-disk state is available to compare.
+    let is_stale = is_current_workspace && options.revision.is_none() && {
+        let working_copy = loaded.workspace.working_copy();
+        let wc_tree_matches = working_copy
+            .tree()
+            .map(|tree| tree.tree_ids() == commit.tree().tree_ids())
+            .unwrap_or(false);
+        !wc_tree_matches && working_copy.operation_id() != repo.op_id()
+    };
+
+    let git_head_behind = colocated && git_head_differs(repo, &commit);
+    let signed = commit.is_signed();
+    let signature_status = if options.verify_signatures && signed {
+        signature_status(&commit)
+    } else {
+        SignatureStatus::Unknown
+    };
+
+    let immutable_heads = loaded.immutable_heads(options)?;
     let immutable = immutable_heads.contains(&wc_id);
 
-    let description = commit
-        .description()
-        .lines()
-        .next()
-        .unwrap_or("")
-        .to_string();
+    let description = first_line(commit.description());
+    let description_full = commit.description().to_string();
+
+    let author_name = commit.author().name.clone();
+    let author_email = commit.author().email.clone();
+    let authored_by_me = is_own_email(loaded.settings.user_email(), &author_email);
+    let committer_email = commit.committer().email.clone();
+
+    let authored_at = timestamp_to_datetime(&commit.author().timestamp)?;
+    let age_seconds = Utc::now().signed_duration_since(authored_at).num_seconds();
+    let age_human = human_age(age_seconds);
 
     let mut bookmarks = Vec::new();
     for (name, _) in view.local_bookmarks_for_commit(&wc_id) {
+        let (has_remote, is_synced, needs_push) = check_remote_sync(repo, name.as_str(), options)?;
         bookmarks.push(Bookmark {
             name: name.as_str().to_string(),
             distance: 0,
+            target: change_id_full.clone(),
+            is_tracked: !options.no_remote && is_tracked_locally(view, name.as_str()),
+            has_remote,
+            is_synced,
+            needs_push,
         });
     }
 
-    let ancestor_bookmarks = find_ancestor_bookmarks(&repo, view, &wc_id, &immutable_heads, 10)?;
-    bookmarks.extend(ancestor_bookmarks);
+    let wc = RevsetExpression::commit(wc_id.clone());
+    let descendant_count = count_revset(repo, &wc.descendants().minus(&wc), MAX_DESCENDANTS)?;
+
+    let walk_started = Instant::now();
+    let mut commits_visited = 0;
+    if bookmarks.is_empty() || !options.nearest_bookmark_only {
+        let (ancestor_bookmarks, visited) =
+            find_ancestor_bookmarks(repo, view, &wc_id, &immutable_heads, options)?;
+        bookmarks.extend(ancestor_bookmarks);
+        commits_visited = visited;
+    }
+    let ancestor_walk_ms = elapsed_ms(walk_started);
+
+    let (has_remote, is_synced, needs_push) = bookmarks.first().map_or((false, true, false), |b| {
+        (b.has_remote, b.is_synced, b.needs_push)
+    });
 
-    let (has_remote, is_synced) = check_remote_sync(view, &bookmarks);
+    let (has_trunk, on_trunk, ahead, behind) = match find_trunk_head(view, options) {
+        Some(trunk_id) => {
+            let on_trunk = repo
+                .index()
+                .is_ancestor(&wc_id, &trunk_id)
+                .map_err(|e| Error::Jj(format!("check ancestry: {e}")))?;
+            let ahead = count_exclusive_ancestors(repo, &wc_id, &trunk_id)?;
+            let behind = count_exclusive_ancestors(repo, &trunk_id, &wc_id)?;
+            (true, on_trunk, ahead, behind)
+        }
+        None => (false, false, 0, 0),
+    };
 
-    Ok(Some(JjStatus {
-        repo_root: repo_root.to_string_lossy().to_string(),
+    Ok(JjStatus {
+        repo_root: loaded.root.to_string_lossy().to_string(),
+        path_in_repo: path_in_repo(path, &loaded.root),
+        operation_id: repo.op_id().hex(),
+        colocated,
+        degraded: false,
         change_id,
+        change_id_full,
         change_id_prefix_len,
+        short_id_len,
+        commit_id,
+        commit_id_prefix_len,
+        parents,
+        is_merge,
+        is_root,
         bookmarks,
         description,
+        description_full,
+        author_name,
+        author_email,
+        authored_by_me,
+        committer_email,
+        authored_at,
+        age_seconds,
+        age_human,
         empty,
+        files_added,
+        files_modified,
+        files_deleted,
+        lines_added,
+        lines_removed,
+        new_files,
+        dirty,
         conflict,
+        conflict_count,
+        conflicted_files,
+        conflicts,
         divergent,
+        divergent_count,
         hidden,
+        hidden_reason,
         immutable,
+        is_stale,
+        git_head_behind,
+        signed,
+        signature_status,
         has_remote,
         is_synced,
-    }))
+        needs_push,
+        has_trunk,
+        on_trunk,
+        ahead,
+        behind,
+        descendant_count,
+        diagnostics: Diagnostics {
+            load_ms,
+            ancestor_walk_ms,
+            commits_visited,
+            total_ms: elapsed_ms(started),
+            cached: false,
+        },
+    })
 }
 
-fn find_repo_root(start: &Path) -> Option<PathBuf> {
-    let mut current = start.to_path_buf();
-    loop {
-        if current.join(".jj").is_dir() {
-            return Some(current);
-        }
-        if !current.pop() {
-            return None;
-        }
+/// Status for a repo whose working-copy commit is missing from the store.
+fn degraded_status(loaded: &LoadedRepo, path: &Path, colocated: bool) -> JjStatus {
+    JjStatus {
+        repo_root: loaded.root.to_string_lossy().to_string(),
+        path_in_repo: path_in_repo(path, &loaded.root),
+        operation_id: loaded.repo.op_id().hex(),
+        colocated,
+        degraded: true,
+        short_id_len: configured_short_id_len(&loaded.settings).unwrap_or(8),
+        // Vacuously synced, as for a bookmark without remotes.
+        is_synced: true,
+        ..JjStatus::default()
     }
 }
 
-fn create_user_settings() -> Result<UserSettings> {
-    let mut config = StackedConfig::with_defaults();
-    let mut layer = ConfigLayer::empty(ConfigSource::User);
-    layer
-        .set_value("user.name", "nu_plugin_jj")
-        .map_err(|e| Error::Jj(format!("set user.name: {e}")))?;
-    layer
-        .set_value("user.email", "nu_plugin_jj@localhost")
-        .map_err(|e| Error::Jj(format!("set user.email: {e}")))?;
-    config.add_layer(layer);
-    UserSettings::from_config(config).map_err(|e| Error::Jj(format!("settings: {e}")))
+/// Lists up to `limit` ancestors of the working-copy commit (inclusive),
+/// breadth-first.
+pub fn log(path: &Path, limit: usize, settings: &SettingsCache) -> Result<Vec<LogEntry>> {
+    let loaded = load_repo(path, &RootSearch::default(), settings)?;
+
+    let wc_id = loaded.wc_commit_id()?;
+
+    let mut entries = Vec::new();
+    walk_ancestors(&loaded.repo, wc_id, limit, |commit| {
+        entries.push(LogEntry {
+            change_id: short_change_id(&encode_reverse_hex(commit.change_id().as_bytes())),
+            commit_id: commit.id().hex(),
+            description: first_line(commit.description()),
+            author_email: commit.author().email.clone(),
+            authored_at: timestamp_to_datetime(&commit.author().timestamp)?,
+        });
+        Ok(())
+    })?;
+
+    Ok(entries)
 }
 
-fn find_immutable_heads(view: &jj_lib::view::View) -> HashSet<CommitId> {
-    let mut immutable = HashSet::new();
+/// How many commits `log` would return, without building the entries.
+pub fn log_count(path: &Path, limit: usize, settings: &SettingsCache) -> Result<usize> {
+    let loaded = load_repo(path, &RootSearch::default(), settings)?;
 
-    for (symbol, remote_ref) in
-        view.remote_bookmarks_matching(&StringMatcher::All, &StringMatcher::All)
-    {
-        let name = symbol.name.as_str();
-        let remote = symbol.remote.as_str();
+    let wc_id = loaded.wc_commit_id()?;
+
+    walk_ancestors(&loaded.repo, wc_id, limit, |_| Ok(()))
+}
+
+/// Calls `visit` on up to `limit` ancestors of `start` (inclusive),
+/// breadth-first, and returns how many were visited.
+fn walk_ancestors(
+    repo: &Arc<ReadonlyRepo>,
+    start: CommitId,
+    limit: usize,
+    mut visit: impl FnMut(&Commit) -> Result<()>,
+) -> Result<usize> {
+    let mut queue: VecDeque<CommitId> = VecDeque::from([start]);
+    let mut visited = HashSet::new();
 
-        if remote == "git" {
+    while let Some(commit_id) = queue.pop_front() {
+        if visited.len() >= limit {
+            break;
+        }
+        if !visited.insert(commit_id.clone()) {
             continue;
         }
 
-        let is_trunk =
-            matches!(remote, "origin" | "upstream") && matches!(name, "main" | "master" | "trunk");
-        let is_untracked = view.get_local_bookmark(symbol.name).is_absent();
+        let commit = repo
+            .store()
+            .get_commit(&commit_id)
+            .map_err(|e| Error::Jj(format!("get commit: {e}")))?;
+        visit(&commit)?;
 
-        if is_trunk || is_untracked {
-            if let Some(id) = remote_ref.target.as_normal() {
-                immutable.insert(id.clone());
-            }
+        for parent_id in commit.parent_ids() {
+            queue.push_back(parent_id.clone());
         }
     }
 
-    for (_, target) in view.tags() {
-        if let Some(id) = target.local_target.as_normal() {
-            immutable.insert(id.clone());
+    Ok(visited.len())
+}
+
+/// Lists every local bookmark followed by every remote bookmark (excluding
+/// the synthetic `git` remote).
+pub fn bookmarks(path: &Path, settings: &SettingsCache) -> Result<Vec<BookmarkRef>> {
+    let loaded = load_repo(path, &RootSearch::default(), settings)?;
+
+    let repo = &loaded.repo;
+    let view = repo.view();
+    let mut result = Vec::new();
+
+    for (name, target) in view.local_bookmarks() {
+        result.push(BookmarkRef {
+            name: name.as_str().to_string(),
+            remote: None,
+            target_change_id: target_change_id(repo, target)?,
+            is_tracked: is_tracked_locally(view, name.as_str()),
+        });
+    }
+
+    for (symbol, remote_ref) in
+        view.remote_bookmarks_matching(&StringMatcher::All, &StringMatcher::All)
+    {
+        if symbol.remote.as_str() == "git" {
+            continue;
         }
+
+        result.push(BookmarkRef {
+            name: symbol.name.as_str().to_string(),
+            remote: Some(symbol.remote.as_str().to_string()),
+            target_change_id: target_change_id(repo, &remote_ref.target)?,
+            is_tracked: remote_ref.is_tracked(),
+        });
     }
 
-    immutable
+    Ok(result)
 }
 
-fn find_ancestor_bookmarks(
-    repo: &std::sync::Arc<jj_lib::repo::ReadonlyRepo>,
-    view: &jj_lib::view::View,
-    wc_id: &CommitId,
-    immutable_heads: &HashSet<CommitId>,
+/// How many commits the working copy is from the local bookmark `name`:
+/// 0 when the bookmark points at the working-copy commit, otherwise the
+/// nearest ancestor distance. `None` when the bookmark isn't an ancestor
+/// within `max_depth` generations.
+pub fn bookmark_distance(
+    path: &Path,
+    name: &str,
     max_depth: usize,
-) -> Result<Vec<Bookmark>> {
-    let mut queue: VecDeque<(CommitId, usize)> = VecDeque::new();
-    let mut visited = HashSet::new();
-    let mut found: HashMap<String, usize> = HashMap::new();
+    settings: &SettingsCache,
+) -> Result<Option<usize>> {
+    let loaded = load_repo(path, &RootSearch::default(), settings)?;
 
-    let wc_commit = repo
+    let wc_id = loaded.wc_commit_id()?;
+
+    let repo = &loaded.repo;
+    let view = repo.view();
+    let target = view.get_local_bookmark(jj_lib::ref_name::RefName::new(name));
+    if target.is_absent() {
+        return Err(Error::UnknownBookmark(name.to_string()));
+    }
+    if target.added_ids().any(|id| *id == wc_id) {
+        return Ok(Some(0));
+    }
+
+    let (found, _) = ancestor_bookmark_distances(repo, view, &wc_id, &HashSet::new(), max_depth)?;
+    Ok(found.get(name).map(|(distance, _)| *distance))
+}
+
+/// Lists files changed in the working-copy commit relative to its parent.
+pub fn changed_files(path: &Path, settings: &SettingsCache) -> Result<Vec<FileChange>> {
+    let loaded = load_repo(path, &RootSearch::default(), settings)?;
+
+    let wc_id = loaded.wc_commit_id()?;
+
+    let repo = &loaded.repo;
+    let commit = repo
         .store()
-        .get_commit(wc_id)
+        .get_commit(&wc_id)
         .map_err(|e| Error::Jj(format!("get commit: {e}")))?;
 
-    for parent_id in wc_commit.parent_ids() {
-        queue.push_back((parent_id.clone(), 1));
+    let changes = diff_against_parent(repo, &commit)?
+        .into_iter()
+        .map(|change| FileChange {
+            path: change.path.as_internal_file_string().to_string(),
+            status: change.status,
+        })
+        .collect();
+
+    Ok(changes)
+}
+
+/// `path` relative to the workspace root `root`, `/`-separated, or empty when
+/// `path` is the root itself. `None` when `path` isn't under `root`.
+pub fn path_in_repo(path: &Path, root: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    Some(
+        relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+    )
+}
+
+/// Conflicted files in the working-copy commit, or in the single commit
+/// `revision` names. Empty when the commit has no conflicts.
+pub fn conflicts(
+    path: &Path,
+    revision: Option<&str>,
+    settings: &SettingsCache,
+) -> Result<Vec<Conflict>> {
+    let loaded = load_repo(path, &RootSearch::default(), settings)?;
+
+    let commit_id = match revision {
+        Some(revision) => loaded.resolve_single_revision(revision)?,
+        None => loaded.wc_commit_id()?,
+    };
+
+    let commit = loaded
+        .repo
+        .store()
+        .get_commit(&commit_id)
+        .map_err(|e| Error::Jj(format!("get commit: {e}")))?;
+
+    if !commit.has_conflict() {
+        return Ok(Vec::new());
     }
+    Ok(tree_conflicts(&commit))
+}
 
-    while let Some((commit_id, depth)) = queue.pop_front() {
-        if depth > max_depth {
-            continue;
-        }
-        if !visited.insert(commit_id.clone()) {
-            continue;
-        }
+/// Full change IDs of the first `limit` commits `revset` resolves to, in
+/// revset order (newest first), using the user's revset aliases.
+pub fn resolve(
+    path: &Path,
+    revset: &str,
+    limit: usize,
+    settings: &SettingsCache,
+) -> Result<Vec<String>> {
+    let loaded = load_repo(path, &RootSearch::default(), settings)?;
 
-        for (bookmark_name, _) in view.local_bookmarks_for_commit(&commit_id) {
-            let name = bookmark_name.as_str().to_string();
-            found.entry(name).or_insert(depth);
-        }
+    loaded
+        .evaluate_revset(revset, Some(limit))?
+        .iter()
+        .map(|id| full_change_id(&loaded.repo, id))
+        .collect()
+}
 
-        if immutable_heads.contains(&commit_id) {
-            continue;
-        }
+/// The working-copy commit's full description, every line included.
+pub fn description(path: &Path, settings: &SettingsCache) -> Result<String> {
+    let loaded = load_repo(path, &RootSearch::default(), settings)?;
 
-        if depth < max_depth {
-            let commit = repo
-                .store()
-                .get_commit(&commit_id)
-                .map_err(|e| Error::Jj(format!("get commit: {e}")))?;
-            for parent_id in commit.parent_ids() {
-                queue.push_back((parent_id.clone(), depth + 1));
-            }
-        }
+    let wc_id = loaded.wc_commit_id()?;
+
+    let commit = loaded
+        .repo
+        .store()
+        .get_commit(&wc_id)
+        .map_err(|e| Error::Jj(format!("get commit: {e}")))?;
+
+    Ok(commit.description().to_string())
+}
+
+/// `root` with a leading `home` replaced by `~`; unchanged when it isn't
+/// under `home`.
+pub fn abbreviate_home(root: &Path, home: Option<&Path>) -> String {
+    match home.and_then(|home| root.strip_prefix(home).ok()) {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Some(rest) => Path::new("~").join(rest).to_string_lossy().to_string(),
+        None => root.to_string_lossy().to_string(),
     }
+}
 
-    let mut result: Vec<Bookmark> = found
-        .into_iter()
-        .map(|(name, distance)| Bookmark { name, distance })
-        .collect();
-    result.sort_by_key(|b| b.distance);
-    Ok(result)
+/// The workspace root containing `path`, found without loading the repo.
+pub fn root(path: &Path) -> Result<PathBuf> {
+    find_repo_root(path, &RootSearch::default()).ok_or(Error::NotARepository)
 }
 
-fn check_remote_sync(view: &jj_lib::view::View, bookmarks: &[Bookmark]) -> (bool, bool) {
-    if bookmarks.is_empty() {
-        return (false, true);
+/// Unified diffs of each file changed in the working-copy commit against its
+/// parent, with `context` unchanged lines around every hunk.
+pub fn diff(path: &Path, context: usize, settings: &SettingsCache) -> Result<Vec<FileDiff>> {
+    let loaded = load_repo(path, &RootSearch::default(), settings)?;
+
+    let wc_id = loaded.wc_commit_id()?;
+
+    let repo = &loaded.repo;
+    let commit = repo
+        .store()
+        .get_commit(&wc_id)
+        .map_err(|e| Error::Jj(format!("get commit: {e}")))?;
+
+    let mut diffs = Vec::new();
+    for change in diff_against_parent(repo, &commit)? {
+        let path = change.path.as_internal_file_string().to_string();
+        let old_name = match change.status {
+            FileStatus::Added => "/dev/null".to_string(),
+            _ => format!("a/{path}"),
+        };
+        let new_name = match change.status {
+            FileStatus::Deleted => "/dev/null".to_string(),
+            _ => format!("b/{path}"),
+        };
+
+        let before = read_text_file(repo, &change.path, change.before)?;
+        let after = read_text_file(repo, &change.path, change.after)?;
+        let diff = match (before, after) {
+            (Some(before), Some(after)) => format!(
+                "--- {old_name}\n+++ {new_name}\n{}",
+                unified_hunks(&before, &after, context)
+            ),
+            _ => format!("Binary files {old_name} and {new_name} differ\n"),
+        };
+
+        diffs.push(FileDiff {
+            path,
+            status: change.status,
+            diff,
+        });
     }
 
-    let bm_name = &bookmarks[0].name;
-    let local_target = view.get_local_bookmark(jj_lib::ref_name::RefName::new(bm_name));
+    Ok(diffs)
+}
 
-    let name_matcher = jj_lib::str_util::StringPattern::exact(bm_name).to_matcher();
-    let mut has_remote = false;
-    let mut is_synced = false;
+/// Summarizes the working-copy commit of every workspace in the repo.
+pub fn workspaces(path: &Path, settings: &SettingsCache) -> Result<Vec<WorkspaceEntry>> {
+    let loaded = load_repo(path, &RootSearch::default(), settings)?;
 
-    for (symbol, remote_ref) in view.remote_bookmarks_matching(&name_matcher, &StringMatcher::All) {
-        if symbol.remote.as_str() == "git" {
-            continue;
-        }
-        has_remote = true;
-        if remote_ref.target == *local_target {
-            is_synced = true;
-            break;
-        }
+    let repo = &loaded.repo;
+    let mut entries = Vec::new();
+    for (name, wc_id) in repo.view().wc_commit_ids() {
+        let commit = repo
+            .store()
+            .get_commit(wc_id)
+            .map_err(|e| Error::Jj(format!("get commit: {e}")))?;
+        entries.push(WorkspaceEntry {
+            name: name.as_str().to_string(),
+            change_id: short_change_id(&encode_reverse_hex(commit.change_id().as_bytes())),
+            description: first_line(commit.description()),
+            empty: is_empty_commit(repo, &commit)?,
+        });
     }
 
-    (has_remote, is_synced || !has_remote)
+    Ok(entries)
+}
+
+/// Lists up to `limit` operations, newest first, starting at the head
+/// operation.
+pub fn op_log(path: &Path, limit: usize, settings: &SettingsCache) -> Result<Vec<OpLogEntry>> {
+    let loaded = load_repo(path, &RootSearch::default(), settings)?;
+
+    let mut entries = Vec::new();
+    for op in op_walk::walk_ancestors(std::slice::from_ref(loaded.repo.operation())).take(limit) {
+        let op = op.map_err(|e| Error::Jj(format!("walk operations: {e}")))?;
+        let metadata = op.metadata();
+        entries.push(OpLogEntry {
+            operation_id: op.id().hex(),
+            description: metadata.description.clone(),
+            time_started: timestamp_to_datetime(&metadata.time.start)?,
+            time_ended: timestamp_to_datetime(&metadata.time.end)?,
+            user: format!("{}@{}", metadata.username, metadata.hostname),
+        });
+    }
+
+    Ok(entries)
+}
+
+fn target_change_id(repo: &Arc<ReadonlyRepo>, target: &RefTarget) -> Result<Option<String>> {
+    let Some(id) = target.as_normal() else {
+        return Ok(None);
+    };
+    commit_change_id(repo, id).map(Some)
+}
+
+/// Short change ID of the commit `id`.
+fn commit_change_id(repo: &Arc<ReadonlyRepo>, id: &CommitId) -> Result<String> {
+    full_change_id(repo, id).map(|full| short_change_id(&full))
+}
+
+fn full_change_id(repo: &Arc<ReadonlyRepo>, id: &CommitId) -> Result<String> {
+    let commit = repo
+        .store()
+        .get_commit(id)
+        .map_err(|e| Error::Jj(format!("get commit: {e}")))?;
+    Ok(encode_reverse_hex(commit.change_id().as_bytes()))
+}
+
+fn short_change_id(change_id_full: &str) -> String {
+    change_id_full[..8.min(change_id_full.len())].to_string()
+}
+
+/// Emails compare case-insensitively; the placeholder identity never matches.
+fn is_own_email(user_email: &str, author_email: &str) -> bool {
+    !user_email.is_empty()
+        && user_email != PLACEHOLDER_EMAIL
+        && user_email.eq_ignore_ascii_case(author_email)
+}
+
+fn first_line(description: &str) -> String {
+    description.lines().next().unwrap_or("").to_string()
+}
+
+/// Whether to report a commit as `empty`: jj's notion (an unchanged tree),
+/// optionally also requiring that the commit has no description.
+fn reports_empty(unchanged: bool, description: &str, describe_is_nonempty: bool) -> bool {
+    unchanged && !(describe_is_nonempty && !description.trim().is_empty())
+}
+
+fn is_empty_commit(repo: &Arc<ReadonlyRepo>, commit: &Commit) -> Result<bool> {
+    commit
+        .is_empty(repo.as_ref())
+        .block_on()
+        .map_err(|e| Error::Jj(format!("check empty: {e}")))
+}
+
+/// Returns the repo root for `path` along with a fingerprint of the current
+/// operation heads. The fingerprint changes whenever the repo advances, so it
+/// can be compared without loading the repo. It also covers the workspace's
+/// working-copy state and a colocated repo's git HEAD, which `is_stale` and
+/// `git_head_behind` depend on and which change without a new operation.
+pub fn op_heads_fingerprint(path: &Path, search: &RootSearch) -> Option<(PathBuf, String)> {
+    let repo_root = find_repo_root(path, search)?;
+    let repo_dir = find_repo_dir(&repo_root)?;
+
+    let mut heads: Vec<String> = fs::read_dir(repo_dir.join("op_heads").join("heads"))
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    heads.sort();
+
+    let mut fingerprint = heads.join(",");
+    let checkout = repo_root.join(".jj").join("working_copy").join("checkout");
+    if let Ok(modified) = fs::metadata(checkout).and_then(|m| m.modified()) {
+        let since_epoch = modified
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        fingerprint.push_str(&format!(";wc={}", since_epoch.as_nanos()));
+    }
+    if let Some(git_head) = git_head_fingerprint(&repo_root) {
+        fingerprint.push_str(&format!(";git={git_head}"));
+    }
+
+    Some((repo_root, fingerprint))
+}
+
+/// The colocated `.git/HEAD` plus the commit its branch points at, read as
+/// plain files. A `git checkout` or `git commit` changes one of the two.
+fn git_head_fingerprint(repo_root: &Path) -> Option<String> {
+    let git_dir = repo_root.join(".git");
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    // A ref that only lives in `packed-refs` reads as empty until git next
+    // writes it loose, which committing to it does.
+    let target = head
+        .strip_prefix("ref: ")
+        .and_then(|ref_name| fs::read_to_string(git_dir.join(ref_name)).ok())
+        .unwrap_or_default();
+    Some(format!("{head}@{}", target.trim()))
+}
+
+/// Resolves the `.jj/repo` store directory. Secondary workspaces store the
+/// path to the shared repo in a file instead.
+fn find_repo_dir(repo_root: &Path) -> Option<PathBuf> {
+    let jj_dir = repo_root.join(".jj");
+    let repo_path = jj_dir.join("repo");
+    if repo_path.is_dir() {
+        return Some(repo_path);
+    }
+
+    let target = PathBuf::from(fs::read_to_string(&repo_path).ok()?.trim());
+    if target.is_absolute() {
+        Some(target)
+    } else {
+        Some(jj_dir.join(target))
+    }
+}
+
+/// Walks up from `start` to the first directory containing `.jj`. `is_dir`
+/// follows symlinks, so a `.jj` symlinked to a directory counts.
+fn find_repo_root(start: &Path, search: &RootSearch) -> Option<PathBuf> {
+    if let Some(repository) = &search.repository {
+        let root = if repository.file_name() == Some(OsStr::new(".jj")) {
+            repository.parent()?.to_path_buf()
+        } else {
+            repository.clone()
+        };
+        return root.join(".jj").is_dir().then_some(root);
+    }
+
+    let start_device = if search.same_filesystem {
+        device_id(start)
+    } else {
+        None
+    };
+
+    let mut current = start.to_path_buf();
+    let mut depth = 0;
+    loop {
+        if current.join(".jj").is_dir() {
+            return Some(current);
+        }
+        if search.max_depth.is_some_and(|max| depth >= max) || !current.pop() {
+            return None;
+        }
+        depth += 1;
+        if let Some(device) = start_device
+            && device_id(&current) != Some(device)
+        {
+            return None;
+        }
+    }
+}
+
+#[cfg(unix)]
+fn device_id(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt as _;
+    fs::metadata(path).ok().map(|metadata| metadata.dev())
+}
+
+/// Device ids aren't exposed portably elsewhere, so the boundary check is a
+/// no-op there.
+#[cfg(not(unix))]
+fn device_id(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Compact relative age: `just now` under a minute (or for timestamps in
+/// the future), then whole minutes, hours, days, or weeks (`5m`, `3h`,
+/// `2d`, `6w`).
+pub fn human_age(seconds: i64) -> String {
+    const MINUTE: i64 = 60;
+    const HOUR: i64 = 60 * MINUTE;
+    const DAY: i64 = 24 * HOUR;
+    const WEEK: i64 = 7 * DAY;
+
+    match seconds {
+        ..MINUTE => "just now".to_string(),
+        ..HOUR => format!("{}m", seconds / MINUTE),
+        ..DAY => format!("{}h", seconds / HOUR),
+        ..WEEK => format!("{}d", seconds / DAY),
+        _ => format!("{}w", seconds / WEEK),
+    }
+}
+
+fn elapsed_ms(started: Instant) -> f64 {
+    started.elapsed().as_secs_f64() * 1000.0
+}
+
+/// An out-of-range timezone offset (seen in imported commits) falls back to
+/// UTC rather than failing the whole status.
+fn timestamp_to_datetime(timestamp: &Timestamp) -> Result<DateTime<FixedOffset>> {
+    let offset =
+        FixedOffset::east_opt(timestamp.tz_offset.saturating_mul(60)).unwrap_or_else(|| Utc.fix());
+    DateTime::from_timestamp_millis(timestamp.timestamp.0)
+        .map(|dt| dt.with_timezone(&offset))
+        .ok_or_else(|| Error::Jj(format!("invalid timestamp: {}", timestamp.timestamp.0)))
+}
+
+/// Revset aliases jj-cli ships by default, so user-defined
+/// `immutable_heads()` can build on them.
+const BUILTIN_REVSET_ALIASES: &[(&str, &str)] = &[
+    (
+        "trunk()",
+        r#"latest(
+  remote_bookmarks(exact:"main", exact:"origin") |
+  remote_bookmarks(exact:"master", exact:"origin") |
+  remote_bookmarks(exact:"trunk", exact:"origin") |
+  remote_bookmarks(exact:"main", exact:"upstream") |
+  remote_bookmarks(exact:"master", exact:"upstream") |
+  remote_bookmarks(exact:"trunk", exact:"upstream") |
+  root()
+)"#,
+    ),
+    (
+        "builtin_immutable_heads()",
+        "present(trunk()) | tags() | untracked_remote_bookmarks()",
+    ),
+];
+
+/// `user.email` used when the jj config doesn't set one.
+const PLACEHOLDER_EMAIL: &str = "nu_plugin_jj@localhost";
+
+/// Builds settings from jj's defaults, the user config in `user_paths`
+/// (files or `conf.d`-style directories, usually [`user_config_paths`]) and
+/// the repo's own config.
+pub fn create_user_settings_from(repo_root: &Path, user_paths: &[PathBuf]) -> Result<UserSettings> {
+    let mut config = StackedConfig::with_defaults();
+    let mut layer = ConfigLayer::empty(ConfigSource::Default);
+    layer
+        .set_value("user.name", "nu_plugin_jj")
+        .map_err(|e| Error::Jj(format!("set user.name: {e}")))?;
+    layer
+        .set_value("user.email", PLACEHOLDER_EMAIL)
+        .map_err(|e| Error::Jj(format!("set user.email: {e}")))?;
+    for (decl, defn) in BUILTIN_REVSET_ALIASES {
+        layer
+            .set_value(["revset-aliases", *decl], *defn)
+            .map_err(|e| Error::Jj(format!("set revset alias {decl}: {e}")))?;
+    }
+    config.add_layer(layer);
+
+    for path in user_paths {
+        let loaded = if path.is_dir() {
+            config.load_dir(ConfigSource::User, path)
+        } else if path.is_file() {
+            config.load_file(ConfigSource::User, path)
+        } else {
+            continue;
+        };
+        loaded.map_err(|e| Error::Jj(format!("load config {}: {e}", path.display())))?;
+    }
+
+    let repo_dir = find_repo_dir(repo_root);
+    if let Some(repo_dir) = &repo_dir {
+        let repo_config = repo_dir.join("config.toml");
+        if repo_config.is_file() {
+            config
+                .load_file(ConfigSource::Repo, &repo_config)
+                .map_err(|e| Error::Jj(format!("load config {}: {e}", repo_config.display())))?;
+        }
+    }
+
+    // Apply `--when` conditions and `[[--scope]]` tables the way jj-cli
+    // does. No jj subcommand is running, so `commands` conditions never
+    // match.
+    let home = home_dir();
+    let environment: HashMap<String, String> = env::vars().collect();
+    let hostname = ["HOSTNAME", "COMPUTERNAME"]
+        .iter()
+        .find_map(|name| environment.get(*name).cloned())
+        .unwrap_or_default();
+    let context = ConfigResolutionContext {
+        home_dir: home.as_deref(),
+        repo_path: repo_dir.as_deref(),
+        workspace_path: Some(repo_root),
+        command: None,
+        hostname: &hostname,
+        environment: &environment,
+    };
+    let config = config_resolver::resolve(&config, &context)
+        .map_err(|e| Error::Jj(format!("resolve config: {e}")))?;
+
+    UserSettings::from_config(config).map_err(|e| Error::Jj(format!("settings: {e}")))
+}
+
+/// Reads the plugin's own `nu-plugin-jj.short-id-length` key. jj's display
+/// length lives inside arbitrary templates, so it isn't inferred from them.
+fn configured_short_id_len(settings: &UserSettings) -> Option<usize> {
+    let length = settings.get_int(["nu-plugin-jj", "short-id-length"]).ok()?;
+    usize::try_from(length).ok()
+}
+
+/// Lists every config file `create_user_settings_from` reads for
+/// `repo_root` and `user_paths`, including those inside `conf.d`, with their
+/// modification times. Settings built from the same fingerprint can be
+/// reused.
+pub fn config_fingerprint(
+    repo_root: &Path,
+    user_paths: &[PathBuf],
+) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let mut paths = Vec::new();
+    for path in user_paths {
+        if path.is_dir() {
+            let mut entries: Vec<PathBuf> = fs::read_dir(path)
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .collect();
+            entries.sort();
+            paths.push(path.clone());
+            paths.extend(entries);
+        } else {
+            paths.push(path.clone());
+        }
+    }
+    if let Some(repo_dir) = find_repo_dir(repo_root) {
+        paths.push(repo_dir.join("config.toml"));
+    }
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+            (path, modified)
+        })
+        .collect()
+}
+
+/// Mirrors jj-cli's user config lookup: `$JJ_CONFIG` if set, otherwise
+/// `~/.jjconfig.toml` and `jj/{config.toml,conf.d}` under
+/// `$XDG_CONFIG_HOME` (default `~/.config`) and the platform config
+/// directory (`~/Library/Application Support` on macOS, `%APPDATA%` on
+/// Windows).
+pub fn user_config_paths() -> Vec<PathBuf> {
+    if let Some(paths) = env::var_os("JJ_CONFIG") {
+        return env::split_paths(&paths).collect();
+    }
+
+    let home = home_dir();
+    let mut paths = Vec::new();
+    if let Some(home) = &home {
+        paths.push(home.join(".jjconfig.toml"));
+    }
+
+    let xdg_config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".config")));
+    let mut config_dirs: Vec<PathBuf> = xdg_config_dir.into_iter().collect();
+    if let Some(platform_dir) = platform_config_dir(home.as_deref())
+        && !config_dirs.contains(&platform_dir)
+    {
+        config_dirs.push(platform_dir);
+    }
+    for config_dir in config_dirs {
+        paths.push(config_dir.join("jj").join("config.toml"));
+        paths.push(config_dir.join("jj").join("conf.d"));
+    }
+
+    paths
+}
+
+/// The home directory from `HOME`, or `USERPROFILE` on Windows.
+fn home_dir() -> Option<PathBuf> {
+    ["HOME", "USERPROFILE"]
+        .iter()
+        .filter_map(|name| env::var_os(name))
+        .find(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+#[cfg(target_os = "macos")]
+fn platform_config_dir(home: Option<&Path>) -> Option<PathBuf> {
+    home.map(|home| home.join("Library/Application Support"))
+}
+
+#[cfg(windows)]
+fn platform_config_dir(_home: Option<&Path>) -> Option<PathBuf> {
+    env::var_os("APPDATA")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn platform_config_dir(_home: Option<&Path>) -> Option<PathBuf> {
+    None
+}
+
+struct TreeChange {
+    path: RepoPathBuf,
+    status: FileStatus,
+    before: MergedTreeValue,
+    after: MergedTreeValue,
+}
+
+fn diff_against_parent(repo: &Arc<ReadonlyRepo>, commit: &Commit) -> Result<Vec<TreeChange>> {
+    let parent_tree = commit
+        .parent_tree(repo.as_ref())
+        .block_on()
+        .map_err(|e| Error::Jj(format!("get parent tree: {e}")))?;
+    let tree = commit.tree();
+
+    let entries: Vec<_> = parent_tree
+        .diff_stream(&tree, &EverythingMatcher)
+        .collect()
+        .block_on();
+
+    let mut changes = Vec::new();
+    for entry in entries {
+        let diff = entry
+            .values
+            .map_err(|e| Error::Jj(format!("diff trees: {e}")))?;
+        let status = if diff.before.is_absent() {
+            FileStatus::Added
+        } else if diff.after.is_absent() {
+            FileStatus::Deleted
+        } else {
+            FileStatus::Modified
+        };
+        changes.push(TreeChange {
+            path: entry.path,
+            status,
+            before: diff.before,
+            after: diff.after,
+        });
+    }
+
+    Ok(changes)
+}
+
+/// Reads a file's contents for line diffing. Absent files read as empty;
+/// binary files, conflicts, and non-file entries yield `None`.
+fn read_text_file(
+    repo: &Arc<ReadonlyRepo>,
+    path: &RepoPath,
+    value: MergedTreeValue,
+) -> Result<Option<Vec<u8>>> {
+    if value.is_absent() {
+        return Ok(Some(Vec::new()));
+    }
+
+    let materialized = materialize_tree_value(repo.store(), path, value)
+        .block_on()
+        .map_err(|e| Error::Jj(format!("read {}: {e}", path.as_internal_file_string())))?;
+    let MaterializedTreeValue::File(mut file) = materialized else {
+        return Ok(None);
+    };
+    let contents = file
+        .read_all(path)
+        .block_on()
+        .map_err(|e| Error::Jj(format!("read {}: {e}", path.as_internal_file_string())))?;
+
+    if contents.contains(&0) {
+        return Ok(None);
+    }
+    Ok(Some(contents))
+}
+
+/// Returns `(added, removed)` line counts between two file contents.
+fn count_line_changes(before: &[u8], after: &[u8]) -> (usize, usize) {
+    let (mut added, mut removed) = (0, 0);
+    for hunk in ContentDiff::by_line([before, after]).hunks() {
+        if hunk.kind == DiffHunkKind::Different {
+            removed += split_lines(hunk.contents[0]).count();
+            added += split_lines(hunk.contents[1]).count();
+        }
+    }
+    (added, removed)
+}
+
+/// Renders the `@@` hunks of a unified diff from `before` to `after`, keeping
+/// up to `context` unchanged lines around each change. Hunks whose context
+/// would overlap are merged.
+fn unified_hunks(before: &[u8], after: &[u8], context: usize) -> String {
+    let mut lines: Vec<(char, &[u8])> = Vec::new();
+    for hunk in ContentDiff::by_line([before, after]).hunks() {
+        let old: &[u8] = hunk.contents[0];
+        let new: &[u8] = hunk.contents[1];
+        match hunk.kind {
+            DiffHunkKind::Matching => lines.extend(split_lines(old).map(|l| (' ', l))),
+            DiffHunkKind::Different => {
+                lines.extend(split_lines(old).map(|l| ('-', l)));
+                lines.extend(split_lines(new).map(|l| ('+', l)));
+            }
+        }
+    }
+
+    let changed: Vec<usize> = (0..lines.len()).filter(|&i| lines[i].0 != ' ').collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < changed.len() {
+        let mut j = i;
+        while j + 1 < changed.len() && changed[j + 1] - changed[j] - 1 <= 2 * context {
+            j += 1;
+        }
+        let start = changed[i].saturating_sub(context);
+        let end = (changed[j] + 1 + context).min(lines.len());
+
+        let old_before = lines[..start].iter().filter(|(tag, _)| *tag != '+').count();
+        let new_before = lines[..start].iter().filter(|(tag, _)| *tag != '-').count();
+        let hunk = &lines[start..end];
+        let old_len = hunk.iter().filter(|(tag, _)| *tag != '+').count();
+        let new_len = hunk.iter().filter(|(tag, _)| *tag != '-').count();
+        out.push_str(&format!(
+            "@@ -{},{old_len} +{},{new_len} @@\n",
+            old_before + usize::from(old_len > 0),
+            new_before + usize::from(new_len > 0),
+        ));
+
+        for (tag, line) in hunk {
+            out.push(*tag);
+            out.push_str(&String::from_utf8_lossy(line));
+            if !line.ends_with(b"\n") {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+        i = j + 1;
+    }
+    out
+}
+
+fn split_lines(text: &[u8]) -> impl Iterator<Item = &[u8]> {
+    text.split_inclusive(|&b| b == b'\n')
+}
+
+/// Whether the colocated git repo's HEAD no longer points at the working-copy
+/// commit's first parent, which is where jj keeps it.
+fn git_head_differs(repo: &Arc<ReadonlyRepo>, commit: &Commit) -> bool {
+    let Ok(git_backend) = git::get_git_backend(repo.store()) else {
+        return false;
+    };
+    let Ok(head_id) = git_backend.git_repo().head_id() else {
+        return false;
+    };
+    let head_id = CommitId::from_bytes(head_id.as_bytes());
+    commit.parent_ids().first() != Some(&head_id)
+}
+
+/// Verifies the commit's signature with the signing backends configured in
+/// the user's settings. Unsigned commits, and failures to verify, are
+/// `unknown`.
+fn signature_status(commit: &Commit) -> SignatureStatus {
+    match commit.verification() {
+        Ok(Some(verification)) => match verification.status {
+            SigStatus::Good => SignatureStatus::Good,
+            SigStatus::Bad => SignatureStatus::Bad,
+            SigStatus::Unknown => SignatureStatus::Unknown,
+        },
+        Ok(None) | Err(_) => SignatureStatus::Unknown,
+    }
+}
+
+/// Conflicted entries in the commit's tree. An entry whose conflict can't be
+/// read is left out rather than failing the whole status.
+fn tree_conflicts(commit: &Commit) -> Vec<Conflict> {
+    commit
+        .tree()
+        .conflicts()
+        .filter_map(|(path, value)| {
+            let value = value.ok()?;
+            Some(Conflict {
+                path: path.as_internal_file_string().to_string(),
+                num_sides: value.num_sides(),
+            })
+        })
+        .collect()
+}
+
+/// Trunk and untracked remote bookmarks plus tags; only tags with
+/// `no_remote`.
+fn find_immutable_heads(view: &jj_lib::view::View, options: &CollectOptions) -> HashSet<CommitId> {
+    let mut immutable = HashSet::new();
+
+    for (_, target) in view.tags() {
+        if let Some(id) = target.local_target.as_normal() {
+            immutable.insert(id.clone());
+        }
+    }
+    if options.no_remote {
+        return immutable;
+    }
+
+    for (symbol, remote_ref) in
+        view.remote_bookmarks_matching(&StringMatcher::All, &StringMatcher::All)
+    {
+        let name = symbol.name.as_str();
+        let remote = symbol.remote.as_str();
+
+        if remote == REMOTE_NAME_FOR_LOCAL_GIT_REPO && !options.include_git_remote {
+            continue;
+        }
+
+        let is_trunk = options.is_trunk(name, remote);
+        let is_untracked = view.get_local_bookmark(symbol.name).is_absent();
+
+        if is_trunk || is_untracked {
+            if let Some(id) = remote_ref.target.as_normal() {
+                immutable.insert(id.clone());
+            }
+        }
+    }
+
+    immutable
+}
+
+fn find_trunk_head(view: &jj_lib::view::View, options: &CollectOptions) -> Option<CommitId> {
+    if options.no_remote {
+        return None;
+    }
+
+    for (symbol, remote_ref) in
+        view.remote_bookmarks_matching(&StringMatcher::All, &StringMatcher::All)
+    {
+        let name = symbol.name.as_str();
+        let remote = symbol.remote.as_str();
+
+        let is_trunk = options.is_trunk(name, remote);
+        if is_trunk {
+            if let Some(id) = remote_ref.target.as_normal() {
+                return Some(id.clone());
+            }
+        }
+    }
+
+    None
+}
+
+/// Counts commits reachable from `head` that are not ancestors of `base`
+/// (`::head ~ ::base`), up to `MAX_TRUNK_DISTANCE`.
+fn count_exclusive_ancestors(
+    repo: &Arc<ReadonlyRepo>,
+    head: &CommitId,
+    base: &CommitId,
+) -> Result<usize> {
+    let expression = RevsetExpression::commit(head.clone())
+        .ancestors()
+        .minus(&RevsetExpression::commit(base.clone()).ancestors());
+    count_revset(repo, &expression, MAX_TRUNK_DISTANCE)
+}
+
+/// Evaluates `expression` and counts its commits, stopping at `limit`.
+fn count_revset(
+    repo: &Arc<ReadonlyRepo>,
+    expression: &Arc<ResolvedRevsetExpression>,
+    limit: usize,
+) -> Result<usize> {
+    let revset = expression
+        .evaluate(repo.as_ref())
+        .map_err(|e| Error::Jj(format!("evaluate revset: {e}")))?;
+    let mut count = 0;
+    for id in revset.iter().take(limit) {
+        id.map_err(|e| Error::Jj(format!("evaluate revset: {e}")))?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn find_ancestor_bookmarks(
+    repo: &Arc<ReadonlyRepo>,
+    view: &jj_lib::view::View,
+    wc_id: &CommitId,
+    immutable_heads: &HashSet<CommitId>,
+    options: &CollectOptions,
+) -> Result<(Vec<Bookmark>, usize)> {
+    let (found, visited) =
+        ancestor_bookmark_distances(repo, view, wc_id, immutable_heads, options.bookmark_depth)?;
+
+    let mut result = Vec::with_capacity(found.len());
+    for (name, (distance, target)) in found {
+        let (has_remote, is_synced, needs_push) = check_remote_sync(repo, &name, options)?;
+        result.push(Bookmark {
+            name,
+            distance,
+            target,
+            is_tracked: !options.no_remote && is_tracked_locally(view, &name),
+            has_remote,
+            is_synced,
+            needs_push,
+        });
+    }
+    sort_bookmarks(&mut result);
+    Ok((result, visited))
+}
+
+/// Breadth-first walk over the ancestors of `wc_id` (excluding itself), up
+/// to `max_depth` generations and not past `immutable_heads`. Returns the
+/// nearest distance and full change ID of every local bookmark seen, plus how
+/// many commits were visited.
+fn ancestor_bookmark_distances(
+    repo: &Arc<ReadonlyRepo>,
+    view: &jj_lib::view::View,
+    wc_id: &CommitId,
+    immutable_heads: &HashSet<CommitId>,
+    max_depth: usize,
+) -> Result<(HashMap<String, (usize, String)>, usize)> {
+    let mut queue: VecDeque<(CommitId, usize)> = VecDeque::new();
+    let mut visited = HashSet::new();
+    let mut found: HashMap<String, (usize, String)> = HashMap::new();
+
+    let wc_commit = repo
+        .store()
+        .get_commit(wc_id)
+        .map_err(|e| Error::Jj(format!("get commit: {e}")))?;
+
+    for parent_id in wc_commit.parent_ids() {
+        queue.push_back((parent_id.clone(), 1));
+    }
+
+    while let Some((commit_id, depth)) = queue.pop_front() {
+        if depth > max_depth {
+            continue;
+        }
+        if !visited.insert(commit_id.clone()) {
+            continue;
+        }
+
+        let names: Vec<String> = view
+            .local_bookmarks_for_commit(&commit_id)
+            .map(|(name, _)| name.as_str().to_string())
+            .filter(|name| !found.contains_key(name))
+            .collect();
+        let expand = depth < max_depth && !immutable_heads.contains(&commit_id);
+        if names.is_empty() && !expand {
+            continue;
+        }
+
+        let commit = repo
+            .store()
+            .get_commit(&commit_id)
+            .map_err(|e| Error::Jj(format!("get commit: {e}")))?;
+        if !names.is_empty() {
+            let change_id = encode_reverse_hex(commit.change_id().as_bytes());
+            for name in names {
+                found.insert(name, (depth, change_id.clone()));
+            }
+        }
+        if expand {
+            for parent_id in commit.parent_ids() {
+                queue.push_back((parent_id.clone(), depth + 1));
+            }
+        }
+    }
+
+    Ok((found, visited.len()))
+}
+
+/// Whether the local bookmark `name` tracks any non-git remote.
+fn is_tracked_locally(view: &jj_lib::view::View, name: &str) -> bool {
+    let name_matcher = StringPattern::exact(name).to_matcher();
+    view.remote_bookmarks_matching(&name_matcher, &StringMatcher::All)
+        .any(|(symbol, remote_ref)| {
+            symbol.remote != REMOTE_NAME_FOR_LOCAL_GIT_REPO && remote_ref.is_tracked()
+        })
+}
+
+/// Orders by distance, then name, so output doesn't depend on `HashMap`
+/// iteration order.
+fn sort_bookmarks(bookmarks: &mut [Bookmark]) {
+    bookmarks.sort_by(|a, b| (a.distance, &a.name).cmp(&(b.distance, &b.name)));
+}
+
+/// Returns `(has_remote, is_synced, needs_push)` for the local bookmark
+/// `bm_name`, compared against `options.remote`, or against the first non-git
+/// remote that has the bookmark when that is `None`. With
+/// `include_git_remote`, the `git` remote is a candidate too, but only after
+/// every real remote.
+fn check_remote_sync(
+    repo: &Arc<ReadonlyRepo>,
+    bm_name: &str,
+    options: &CollectOptions,
+) -> Result<(bool, bool, bool)> {
+    if options.no_remote {
+        return Ok((false, true, false));
+    }
+
+    let view = repo.view();
+    let local_target = view.get_local_bookmark(jj_lib::ref_name::RefName::new(bm_name));
+
+    let name_pattern = if options.ignore_bookmark_case {
+        StringPattern::exact_i(bm_name)
+    } else {
+        StringPattern::exact(bm_name)
+    };
+    let name_matcher = name_pattern.to_matcher();
+    let mut remotes: Vec<(&str, &RefTarget)> = view
+        .remote_bookmarks_matching(&name_matcher, &StringMatcher::All)
+        .filter(|(symbol, _)| {
+            options.include_git_remote || symbol.remote != REMOTE_NAME_FOR_LOCAL_GIT_REPO
+        })
+        .map(|(symbol, remote_ref)| (symbol.remote.as_str(), &remote_ref.target))
+        .collect();
+    remotes.sort_by_key(|(name, _)| *name == REMOTE_NAME_FOR_LOCAL_GIT_REPO);
+
+    let index = repo.index();
+    let remote = options.remote.as_deref();
+    remote_sync_state(local_target, &remotes, remote, |ancestor, descendant| {
+        index
+            .is_ancestor(ancestor, descendant)
+            .map_err(|e| Error::Jj(format!("check ancestry: {e}")))
+    })
+}
+
+/// Sync state against a single remote: the named one, or the first listed.
+/// A bookmark that isn't on that remote is vacuously synced. It needs a push
+/// when the local target descends from the remote one.
+fn remote_sync_state(
+    local_target: &RefTarget,
+    remotes: &[(&str, &RefTarget)],
+    remote: Option<&str>,
+    is_ancestor: impl Fn(&CommitId, &CommitId) -> Result<bool>,
+) -> Result<(bool, bool, bool)> {
+    let compared = match remote {
+        Some(remote) => remotes.iter().find(|(name, _)| *name == remote),
+        None => remotes.first(),
+    };
+    let Some((_, remote_target)) = compared else {
+        return Ok((false, true, false));
+    };
+
+    let is_synced = *remote_target == local_target;
+    let needs_push = match (remote_target.as_normal(), local_target.as_normal()) {
+        (Some(remote_id), Some(local_id)) if !is_synced => is_ancestor(remote_id, local_id)?,
+        _ => false,
+    };
+    Ok((true, is_synced, needs_push))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    use jj_lib::backend::{CommitId, CopyId, MillisSinceEpoch, Timestamp, TreeValue};
+    use jj_lib::commit::Commit;
+    use jj_lib::hex_util::encode_reverse_hex;
+    use jj_lib::merge::Merge;
+    use jj_lib::merged_tree::MergedTreeBuilder;
+    use jj_lib::object_id::ObjectId as _;
+    use jj_lib::op_store::{RefTarget, RemoteRef, RemoteRefState};
+    use jj_lib::ref_name::{RefName, RemoteName, WorkspaceName};
+    use jj_lib::repo::{MutableRepo, ReadonlyRepo, Repo};
+    use jj_lib::repo_path::RepoPathBuf;
+    use jj_lib::rewrite::merge_commit_trees;
+    use jj_lib::workspace::Workspace;
+    use pollster::FutureExt as _;
+
+    use super::{
+        Bookmark, CollectOptions, RootSearch, abbreviate_home, bookmark_distance,
+        check_remote_sync, collect, config_fingerprint, configured_short_id_len, conflicts,
+        count_line_changes, create_user_settings_from, description, find_repo_root, human_age,
+        is_own_email, load_repo, op_heads_fingerprint, path_in_repo, remote_sync_state,
+        reports_empty, resolve, sort_bookmarks, timestamp_to_datetime, unified_hunks,
+    };
+    use crate::cache::{PrefixCache, SettingsCache, StatusCache};
+    use crate::error::Error;
+
+    fn temp_repo(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("nu_plugin_jj-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join(".jj/repo/op_heads/heads")).unwrap();
+        root
+    }
+
+    /// Initializes a real jj repo, backed by jj's local store, in a fresh
+    /// temp dir.
+    fn init_repo(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("nu_plugin_jj-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let settings = create_user_settings_from(&root, &[]).unwrap();
+        Workspace::init_simple(&settings, &root).unwrap();
+        root
+    }
+
+    /// Runs `edit` in a transaction on the repo at `root` and commits it.
+    fn transact(root: &Path, edit: impl FnOnce(&mut MutableRepo)) -> Arc<ReadonlyRepo> {
+        let loaded = load_repo(
+            root,
+            &RootSearch::default(),
+            &SettingsCache::without_user_config(),
+        )
+        .unwrap();
+        let mut tx = loaded.repo.start_transaction();
+        edit(tx.repo_mut());
+        tx.repo_mut().rebase_descendants().unwrap();
+        tx.commit("nu_plugin_jj test").unwrap()
+    }
+
+    /// Writes a commit on `parents` that keeps the first parent's tree.
+    fn write_commit(repo: &mut MutableRepo, parents: &[&CommitId], description: &str) -> Commit {
+        let tree = repo.store().get_commit(parents[0]).unwrap().tree();
+        let parents = parents.iter().map(|id| (*id).clone()).collect();
+        repo.new_commit(parents, tree)
+            .set_description(description)
+            .write()
+            .unwrap()
+    }
+
+    /// Writes a commit on `parent` whose tree sets the file at `path` to
+    /// `contents`.
+    fn write_file_commit(
+        repo: &mut MutableRepo,
+        parent: &CommitId,
+        path: &str,
+        contents: &str,
+    ) -> Commit {
+        let path = RepoPathBuf::from_internal_string(path).unwrap();
+        let store = repo.store().clone();
+        let id = store
+            .write_file(&path, &mut contents.as_bytes())
+            .block_on()
+            .unwrap();
+        let mut builder = MergedTreeBuilder::new(store.get_commit(parent).unwrap().tree());
+        builder.set_or_remove(
+            path,
+            Merge::normal(TreeValue::File {
+                id,
+                executable: false,
+                copy_id: CopyId::placeholder(),
+            }),
+        );
+        let tree = builder.write_tree().block_on().unwrap();
+        repo.new_commit(vec![parent.clone()], tree)
+            .set_description(contents)
+            .write()
+            .unwrap()
+    }
+
+    /// Makes `commit` the default workspace's working-copy commit.
+    fn edit_commit(repo: &mut MutableRepo, commit: &Commit) {
+        repo.edit(WorkspaceName::DEFAULT.to_owned(), commit)
+            .unwrap();
+    }
+
+    fn set_bookmark(repo: &mut MutableRepo, name: &str, commit: &Commit) {
+        repo.set_local_bookmark_target(RefName::new(name), RefTarget::normal(commit.id().clone()));
+    }
+
+    fn set_remote_bookmark(repo: &mut MutableRepo, name: &str, remote: &str, commit: &Commit) {
+        let remote_ref = RemoteRef {
+            target: RefTarget::normal(commit.id().clone()),
+            state: RemoteRefState::Tracked,
+        };
+        repo.set_remote_bookmark(
+            RefName::new(name).to_remote_symbol(RemoteName::new(remote)),
+            remote_ref,
+        );
+    }
+
+    #[test]
+    fn bookmarks_at_equal_distance_sort_by_name() {
+        let mut bookmarks = vec![
+            Bookmark {
+                name: "zeta".to_string(),
+                distance: 2,
+                target: String::new(),
+                is_tracked: false,
+                has_remote: false,
+                is_synced: true,
+                needs_push: false,
+            },
+            Bookmark {
+                name: "beta".to_string(),
+                distance: 1,
+                target: String::new(),
+                is_tracked: false,
+                has_remote: false,
+                is_synced: true,
+                needs_push: false,
+            },
+            Bookmark {
+                name: "alpha".to_string(),
+                distance: 2,
+                target: String::new(),
+                is_tracked: false,
+                has_remote: false,
+                is_synced: true,
+                needs_push: false,
+            },
+        ];
+        sort_bookmarks(&mut bookmarks);
+
+        let order: Vec<&str> = bookmarks.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(order, ["beta", "alpha", "zeta"]);
+    }
+
+    #[test]
+    fn line_changes_count_added_and_removed() {
+        assert_eq!(count_line_changes(b"", b"a\nb\n"), (2, 0));
+        assert_eq!(count_line_changes(b"a\nb\n", b""), (0, 2));
+        assert_eq!(count_line_changes(b"a\nb\nc\n", b"a\nB\nc\nd\n"), (2, 1));
+        assert_eq!(count_line_changes(b"same\n", b"same\n"), (0, 0));
+    }
+
+    #[test]
+    fn unified_hunks_keep_context_and_merge_nearby_changes() {
+        let before = b"a\nb\nc\nd\ne\nf\ng\nh\n";
+        let after = b"a\nB\nc\nd\ne\nf\ng\nH\n";
+
+        assert_eq!(
+            unified_hunks(before, after, 1),
+            "@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n@@ -7,2 +7,2 @@\n g\n-h\n+H\n"
+        );
+        assert_eq!(
+            unified_hunks(before, after, 3),
+            "@@ -1,8 +1,8 @@\n a\n-b\n+B\n c\n d\n e\n f\n g\n-h\n+H\n"
+        );
+    }
+
+    #[test]
+    fn unified_hunks_handle_new_files_and_missing_newlines() {
+        assert_eq!(
+            unified_hunks(b"", b"x\ny", 3),
+            "@@ -0,0 +1,2 @@\n+x\n+y\n\\ No newline at end of file\n"
+        );
+        assert_eq!(unified_hunks(b"same\n", b"same\n", 3), "");
+    }
+
+    #[test]
+    fn short_id_len_comes_from_plugin_config_key() {
+        let root = temp_repo("short-id-len");
+        let settings = |config: &str| {
+            fs::write(root.join(".jj/repo/config.toml"), config).unwrap();
+            create_user_settings_from(&root, &[]).unwrap()
+        };
+
+        let configured = settings("[nu-plugin-jj]\nshort-id-length = 12\n");
+        assert_eq!(configured_short_id_len(&configured), Some(12));
+
+        let negative = settings("[nu-plugin-jj]\nshort-id-length = -1\n");
+        assert_eq!(configured_short_id_len(&negative), None);
+
+        // Templates are left alone.
+        let template = settings("[template-aliases]\n'format_short_id(id)' = 'id.shortest(6)'\n");
+        assert_eq!(configured_short_id_len(&template), None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn invalid_tz_offset_falls_back_to_utc() {
+        let timestamp = |tz_offset| Timestamp {
+            timestamp: MillisSinceEpoch(90_000),
+            tz_offset,
+        };
+        let valid = timestamp_to_datetime(&timestamp(120)).unwrap();
+        assert_eq!(valid.offset().local_minus_utc(), 7200);
+
+        let invalid = timestamp_to_datetime(&timestamp(100_000)).unwrap();
+        assert_eq!(invalid.offset().local_minus_utc(), 0);
+        assert_eq!(invalid.timestamp_millis(), 90_000);
+    }
+
+    #[test]
+    fn human_age_uses_largest_whole_unit() {
+        assert_eq!(human_age(-5), "just now");
+        assert_eq!(human_age(0), "just now");
+        assert_eq!(human_age(59), "just now");
+        assert_eq!(human_age(60), "1m");
+        assert_eq!(human_age(3599), "59m");
+        assert_eq!(human_age(3600), "1h");
+        assert_eq!(human_age(86_399), "23h");
+        assert_eq!(human_age(86_400), "1d");
+        assert_eq!(human_age(604_799), "6d");
+        assert_eq!(human_age(604_800), "1w");
+        assert_eq!(human_age(60 * 86_400), "8w");
+    }
+
+    #[test]
+    fn own_email_needs_a_configured_user() {
+        assert!(is_own_email("jane@example.com", "Jane@Example.com"));
+        assert!(!is_own_email("jane@example.com", "joe@example.com"));
+        assert!(!is_own_email("", ""));
+        assert!(!is_own_email(
+            "nu_plugin_jj@localhost",
+            "nu_plugin_jj@localhost"
+        ));
+    }
+
+    #[test]
+    fn home_is_abbreviated_in_root() {
+        let home = Path::new("/home/jane");
+        assert_eq!(
+            abbreviate_home(Path::new("/home/jane/src/repo"), Some(home)),
+            Path::new("~").join("src/repo").to_string_lossy()
+        );
+        assert_eq!(abbreviate_home(Path::new("/home/jane"), Some(home)), "~");
+        assert_eq!(
+            abbreviate_home(Path::new("/home/janet/repo"), Some(home)),
+            "/home/janet/repo"
+        );
+        assert_eq!(abbreviate_home(Path::new("/srv/repo"), None), "/srv/repo");
+    }
+
+    #[test]
+    fn path_in_repo_is_relative_to_root() {
+        let root = PathBuf::from("/work/repo");
+        assert_eq!(
+            path_in_repo(&root.join("src").join("bin"), &root),
+            Some("src/bin".to_string())
+        );
+        assert_eq!(path_in_repo(&root, &root), Some(String::new()));
+        assert_eq!(path_in_repo(Path::new("/elsewhere"), &root), None);
+    }
+
+    #[test]
+    fn trunk_matching_uses_configured_lists() {
+        let default = CollectOptions::default();
+        assert!(default.is_trunk("main", "origin"));
+        assert!(!default.is_trunk("develop", "origin"));
+
+        let custom = CollectOptions {
+            trunk_bookmarks: vec!["develop".into()],
+            trunk_remotes: vec!["gitlab".into()],
+            ..CollectOptions::default()
+        };
+        assert!(custom.is_trunk("develop", "gitlab"));
+        assert!(!custom.is_trunk("main", "origin"));
+        assert!(!custom.is_trunk("develop", "origin"));
+    }
+
+    #[test]
+    fn fingerprint_tracks_op_heads() {
+        let root = temp_repo("fingerprint");
+        let heads = root.join(".jj/repo/op_heads/heads");
+        fs::write(heads.join("aaaa"), "").unwrap();
+
+        let (found_root, before) =
+            op_heads_fingerprint(&root.join("src"), &RootSearch::default()).unwrap();
+        assert_eq!(found_root, root);
+        assert_eq!(before, "aaaa");
+
+        fs::remove_file(heads.join("aaaa")).unwrap();
+        fs::write(heads.join("bbbb"), "").unwrap();
+        let (_, after) = op_heads_fingerprint(&root, &RootSearch::default()).unwrap();
+        assert_ne!(before, after);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn fingerprint_tracks_colocated_git_head() {
+        let root = temp_repo("fingerprint-git");
+        fs::write(root.join(".jj/repo/op_heads/heads/aaaa"), "").unwrap();
+        fs::create_dir_all(root.join(".git/refs/heads")).unwrap();
+        fs::write(root.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::write(root.join(".git/refs/heads/main"), "1111\n").unwrap();
+        let (_, before) = op_heads_fingerprint(&root, &RootSearch::default()).unwrap();
+
+        // `git commit` moves the branch without touching the op heads.
+        fs::write(root.join(".git/refs/heads/main"), "2222\n").unwrap();
+        let (_, committed) = op_heads_fingerprint(&root, &RootSearch::default()).unwrap();
+        assert_ne!(before, committed);
+
+        fs::write(root.join(".git/HEAD"), "3333\n").unwrap();
+        let (_, detached) = op_heads_fingerprint(&root, &RootSearch::default()).unwrap();
+        assert_ne!(committed, detached);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn fingerprint_tracks_working_copy_state() {
+        let root = temp_repo("fingerprint-wc");
+        fs::write(root.join(".jj/repo/op_heads/heads/aaaa"), "").unwrap();
+        let (_, without_wc) = op_heads_fingerprint(&root, &RootSearch::default()).unwrap();
+
+        let checkout = root.join(".jj/working_copy/checkout");
+        fs::create_dir_all(checkout.parent().unwrap()).unwrap();
+        fs::write(&checkout, "").unwrap();
+        let file = fs::File::options().write(true).open(&checkout).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1))
+            .unwrap();
+        let (_, before) = op_heads_fingerprint(&root, &RootSearch::default()).unwrap();
+        assert_ne!(without_wc, before);
+
+        // `jj workspace update-stale` rewrites the state without a new op head.
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(2))
+            .unwrap();
+        let (_, after) = op_heads_fingerprint(&root, &RootSearch::default()).unwrap();
+        assert_ne!(before, after);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn sync_state_compares_against_a_single_remote() {
+        let local = RefTarget::normal(CommitId::from_hex("aa"));
+        let stale = RefTarget::normal(CommitId::from_hex("bb"));
+        let remotes = [("origin", &stale), ("upstream", &local)];
+        let unrelated = |_: &CommitId, _: &CommitId| Ok(false);
+
+        // Matching some other remote isn't enough.
+        let state = |remote| remote_sync_state(&local, &remotes, remote, unrelated).unwrap();
+        assert_eq!(state(None), (true, false, false));
+        assert_eq!(state(Some("upstream")), (true, true, false));
+        assert_eq!(state(Some("origin")), (true, false, false));
+        assert_eq!(state(Some("fork")), (false, true, false));
+        assert_eq!(
+            remote_sync_state(&local, &[], None, unrelated).unwrap(),
+            (false, true, false)
+        );
+    }
+
+    #[test]
+    fn bookmark_distance_counts_generations_to_the_bookmark() {
+        let root = init_repo("bookmark-distance");
+        transact(&root, |repo| {
+            let root_id = repo.store().root_commit_id().clone();
+            let base = write_commit(repo, &[&root_id], "base");
+            let middle = write_commit(repo, &[base.id()], "middle");
+            let wc = write_commit(repo, &[middle.id()], "wc");
+            set_bookmark(repo, "main", &base);
+            set_bookmark(repo, "here", &wc);
+            edit_commit(repo, &wc);
+        });
+        let settings = SettingsCache::without_user_config();
+
+        assert_eq!(
+            bookmark_distance(&root, "here", 10, &settings).unwrap(),
+            Some(0)
+        );
+        assert_eq!(
+            bookmark_distance(&root, "main", 10, &settings).unwrap(),
+            Some(2)
+        );
+        assert_eq!(
+            bookmark_distance(&root, "main", 1, &settings).unwrap(),
+            None
+        );
+        assert!(matches!(
+            bookmark_distance(&root, "missing", 10, &settings),
+            Err(Error::UnknownBookmark(name)) if name == "missing"
+        ));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn description_returns_every_line_of_the_working_copy_commit() {
+        let root = init_repo("describe");
+        transact(&root, |repo| {
+            let root_id = repo.store().root_commit_id().clone();
+            let wc = write_commit(repo, &[&root_id], "subject\n\nbody line\n");
+            edit_commit(repo, &wc);
+        });
+        fs::create_dir_all(root.join("src")).unwrap();
+
+        let settings = SettingsCache::without_user_config();
+        assert_eq!(
+            description(&root, &settings).unwrap(),
+            "subject\n\nbody line\n"
+        );
+        assert_eq!(
+            description(&root.join("src"), &settings).unwrap(),
+            "subject\n\nbody line\n"
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn revision_selects_a_commit_other_than_the_working_copy() {
+        let root = init_repo("revision");
+        transact(&root, |repo| {
+            let root_id = repo.store().root_commit_id().clone();
+            let parent = write_commit(repo, &[&root_id], "parent");
+            set_bookmark(repo, "feature", &parent);
+            let wc = write_commit(repo, &[parent.id()], "working copy");
+            edit_commit(repo, &wc);
+        });
+        let collect_at = |revision: Option<&str>| {
+            let options = CollectOptions {
+                revision: revision.map(str::to_string),
+                ..CollectOptions::default()
+            };
+            collect(
+                &root,
+                &options,
+                &SettingsCache::without_user_config(),
+                &PrefixCache::default(),
+            )
+            .unwrap()
+        };
+
+        let wc = collect_at(None);
+        assert_eq!(wc.description, "working copy");
+        assert_eq!(wc.bookmarks[0].distance, 1);
+
+        let parent = collect_at(Some("@-"));
+        assert_eq!(parent.description, "parent");
+        assert_ne!(parent.change_id_full, wc.change_id_full);
+        assert_eq!(parent.bookmarks[0].name, "feature");
+        assert_eq!(parent.bookmarks[0].distance, 0);
+        assert_eq!(parent.descendant_count, 1);
+        assert!(!parent.is_stale);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn resolve_returns_full_change_ids_up_to_the_limit() {
+        let root = init_repo("resolve");
+        let mut change_ids = Vec::new();
+        transact(&root, |repo| {
+            let root_id = repo.store().root_commit_id().clone();
+            let first = write_commit(repo, &[&root_id], "first");
+            let second = write_commit(repo, &[first.id()], "second");
+            set_bookmark(repo, "tip", &second);
+            for commit in [&second, &first] {
+                change_ids.push(encode_reverse_hex(commit.change_id().as_bytes()));
+            }
+        });
+        let settings = SettingsCache::without_user_config();
+
+        let resolved = resolve(&root, "::tip ~ root()", 10, &settings).unwrap();
+        assert_eq!(resolved, change_ids);
+        assert!(resolved.iter().all(|id| id.len() > 8));
+
+        let resolved = resolve(&root, "::tip ~ root()", 1, &settings).unwrap();
+        assert_eq!(resolved, change_ids[..1]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn on_trunk_means_an_ancestor_of_the_trunk_head() {
+        let root = init_repo("on-trunk");
+        let mut ids = None;
+        transact(&root, |repo| {
+            let root_id = repo.store().root_commit_id().clone();
+            let base = write_commit(repo, &[&root_id], "base");
+            let trunk = write_commit(repo, &[base.id()], "trunk");
+            let side = write_commit(repo, &[base.id()], "side");
+            set_remote_bookmark(repo, "main", "origin", &trunk);
+            ids = Some((base.id().clone(), side.id().clone()));
+        });
+        let (base, side) = ids.unwrap();
+        let status_of = |id: &CommitId| {
+            let options = CollectOptions {
+                revision: Some(id.hex()),
+                ..CollectOptions::default()
+            };
+            collect(
+                &root,
+                &options,
+                &SettingsCache::without_user_config(),
+                &PrefixCache::default(),
+            )
+            .unwrap()
+        };
+
+        let status = status_of(&base);
+        assert!(status.has_trunk);
+        assert!(status.on_trunk);
+        assert_eq!((status.ahead, status.behind), (0, 1));
+
+        let status = status_of(&side);
+        assert!(status.has_trunk);
+        assert!(!status.on_trunk);
+        assert_eq!((status.ahead, status.behind), (1, 1));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn conflicts_lists_conflicted_files() {
+        let root = init_repo("conflicts");
+        transact(&root, |repo| {
+            let root_id = repo.store().root_commit_id().clone();
+            let base = write_file_commit(repo, &root_id, "file", "base\n");
+            let left = write_file_commit(repo, base.id(), "file", "left\n");
+            let right = write_file_commit(repo, base.id(), "file", "right\n");
+            let tree = merge_commit_trees(repo, &[left.clone(), right.clone()])
+                .block_on()
+                .unwrap();
+            let merge = repo
+                .new_commit(vec![left.id().clone(), right.id().clone()], tree)
+                .write()
+                .unwrap();
+            edit_commit(repo, &merge);
+        });
+        let settings = SettingsCache::without_user_config();
+
+        let found = conflicts(&root, None, &settings).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].path, "file");
+        assert_eq!(found[0].num_sides, 2);
+
+        assert!(
+            conflicts(&root, Some("root()"), &settings)
+                .unwrap()
+                .is_empty()
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn change_id_prefix_len_is_the_shortest_unique_prefix() {
+        let root = init_repo("prefix-len");
+        let mut wc_commit = None;
+        let repo = transact(&root, |repo| {
+            let root_id = repo.store().root_commit_id().clone();
+            let mut parent = write_commit(repo, &[&root_id], "0");
+            for i in 1..20 {
+                parent = write_commit(repo, &[parent.id()], &i.to_string());
+            }
+            edit_commit(repo, &parent);
+            wc_commit = Some(parent);
+        });
+        let expected = repo
+            .shortest_unique_change_id_prefix_len(wc_commit.unwrap().change_id())
+            .unwrap();
+
+        let settings = SettingsCache::without_user_config();
+        let options = CollectOptions::default();
+        let uncached = collect(&root, &options, &settings, &PrefixCache::default()).unwrap();
+        assert_eq!(uncached.change_id_prefix_len, expected);
+
+        // A second collect reads the length from the prefix cache; a status
+        // with different options misses the status cache but not this one.
+        let prefixes = PrefixCache::default();
+        collect(&root, &options, &settings, &prefixes).unwrap();
+        let options = CollectOptions {
+            no_remote: true,
+            ..CollectOptions::default()
+        };
+        let cached = collect(&root, &options, &settings, &prefixes).unwrap();
+        assert_eq!(cached.change_id_prefix_len, expected);
+        let reused = prefixes.get_or_compute(
+            Path::new(&cached.repo_root),
+            &cached.operation_id,
+            &cached.change_id_full,
+            || unreachable!("prefix length should be cached"),
+        );
+        assert_eq!(reused, expected);
+
+        let cache = StatusCache::default();
+        cache
+            .collect(&root, &options, &settings, &prefixes)
+            .unwrap();
+        let cached = cache
+            .collect(&root, &options, &settings, &prefixes)
+            .unwrap();
+        assert!(cached.diagnostics.cached);
+        assert_eq!(cached.change_id_prefix_len, expected);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn hidden_reason_tells_rewritten_from_abandoned() {
+        let root = init_repo("hidden-reason");
+        let mut ids = None;
+        transact(&root, |repo| {
+            let root_id = repo.store().root_commit_id().clone();
+            let rewritten = write_commit(repo, &[&root_id], "rewritten");
+            let abandoned = write_commit(repo, &[&root_id], "abandoned");
+            ids = Some((rewritten.id().clone(), abandoned.id().clone()));
+        });
+        let (rewritten, abandoned) = ids.unwrap();
+        transact(&root, |repo| {
+            let commit = repo.store().get_commit(&rewritten).unwrap();
+            repo.rewrite_commit(&commit)
+                .set_description("rewritten again")
+                .write()
+                .unwrap();
+            let commit = repo.store().get_commit(&abandoned).unwrap();
+            repo.record_abandoned_commit(&commit);
+        });
+
+        let status_of = |id: &CommitId| {
+            let options = CollectOptions {
+                revision: Some(id.hex()),
+                ..CollectOptions::default()
+            };
+            collect(
+                &root,
+                &options,
+                &SettingsCache::without_user_config(),
+                &PrefixCache::default(),
+            )
+            .unwrap()
+        };
+
+        let status = status_of(&rewritten);
+        assert!(status.hidden);
+        assert_eq!(status.hidden_reason.as_deref(), Some("obsoleted"));
+
+        let status = status_of(&abandoned);
+        assert!(status.hidden);
+        assert_eq!(status.hidden_reason.as_deref(), Some("abandoned"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn sync_state_reads_diverging_remotes_from_the_view() {
+        let root = init_repo("diverging-remotes");
+        let repo = transact(&root, |repo| {
+            let root_id = repo.store().root_commit_id().clone();
+            let base = write_commit(repo, &[&root_id], "base");
+            let local = write_commit(repo, &[base.id()], "local");
+            set_bookmark(repo, "feature", &local);
+            set_remote_bookmark(repo, "feature", "origin", &base);
+            set_remote_bookmark(repo, "feature", "upstream", &local);
+        });
+
+        // Without --remote the first remote (origin) is compared, and it's
+        // behind the local bookmark.
+        let options = CollectOptions::default();
+        assert_eq!(
+            check_remote_sync(&repo, "feature", &options).unwrap(),
+            (true, false, true)
+        );
+
+        let upstream = CollectOptions {
+            remote: Some("upstream".to_string()),
+            ..CollectOptions::default()
+        };
+        assert_eq!(
+            check_remote_sync(&repo, "feature", &upstream).unwrap(),
+            (true, true, false)
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn ignore_bookmark_case_matches_remote_bookmarks_case_insensitively() {
+        let root = init_repo("bookmark-case");
+        let repo = transact(&root, |repo| {
+            let root_id = repo.store().root_commit_id().clone();
+            let commit = write_commit(repo, &[&root_id], "main");
+            set_bookmark(repo, "Main", &commit);
+            set_remote_bookmark(repo, "main", "origin", &commit);
+        });
+
+        let options = CollectOptions::default();
+        assert_eq!(
+            check_remote_sync(&repo, "Main", &options).unwrap(),
+            (false, true, false)
+        );
+
+        let ignore_case = CollectOptions {
+            ignore_bookmark_case: true,
+            ..CollectOptions::default()
+        };
+        assert_eq!(
+            check_remote_sync(&repo, "Main", &ignore_case).unwrap(),
+            (true, true, false)
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn include_git_remote_counts_git_only_bookmarks() {
+        let root = init_repo("git-only-remote");
+        let repo = transact(&root, |repo| {
+            let root_id = repo.store().root_commit_id().clone();
+            let commit = write_commit(repo, &[&root_id], "feature");
+            set_bookmark(repo, "feature", &commit);
+            set_remote_bookmark(repo, "feature", "git", &commit);
+        });
+
+        let options = CollectOptions::default();
+        assert_eq!(
+            check_remote_sync(&repo, "feature", &options).unwrap(),
+            (false, true, false)
+        );
+
+        let with_git = CollectOptions {
+            include_git_remote: true,
+            ..CollectOptions::default()
+        };
+        assert_eq!(
+            check_remote_sync(&repo, "feature", &with_git).unwrap(),
+            (true, true, false)
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn include_git_remote_compares_real_remotes_first() {
+        let root = init_repo("git-remote-order");
+        let repo = transact(&root, |repo| {
+            let root_id = repo.store().root_commit_id().clone();
+            let base = write_commit(repo, &[&root_id], "base");
+            let local = write_commit(repo, &[base.id()], "local");
+            set_bookmark(repo, "feature", &local);
+            set_remote_bookmark(repo, "feature", "git", &local);
+            set_remote_bookmark(repo, "feature", "origin", &base);
+        });
+
+        // `git` is in sync, but `origin` is compared first and is behind.
+        let with_git = CollectOptions {
+            include_git_remote: true,
+            ..CollectOptions::default()
+        };
+        assert_eq!(
+            check_remote_sync(&repo, "feature", &with_git).unwrap(),
+            (true, false, true)
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn no_remote_ignores_diverging_remotes() {
+        let root = init_repo("no-remote");
+        transact(&root, |repo| {
+            let root_id = repo.store().root_commit_id().clone();
+            let base = write_commit(repo, &[&root_id], "base");
+            let local = write_commit(repo, &[base.id()], "local");
+            set_bookmark(repo, "main", &local);
+            set_remote_bookmark(repo, "main", "origin", &base);
+            edit_commit(repo, &local);
+        });
+        let options = CollectOptions {
+            no_remote: true,
+            ..CollectOptions::default()
+        };
+
+        let status = collect(
+            &root,
+            &options,
+            &SettingsCache::without_user_config(),
+            &PrefixCache::default(),
+        )
+        .unwrap();
+        let bookmark = &status.bookmarks[0];
+        assert_eq!(bookmark.name, "main");
+        assert!(!bookmark.has_remote);
+        assert!(bookmark.is_synced);
+        assert!(!bookmark.needs_push);
+        assert!(!bookmark.is_tracked);
+        assert!(!status.has_trunk);
+        assert!(!status.immutable);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn needs_push_when_local_descends_from_remote() {
+        let local_id = || CommitId::from_hex("aa");
+        let behind_id = || CommitId::from_hex("bb");
+        let local = RefTarget::normal(local_id());
+        let behind = RefTarget::normal(behind_id());
+        let remotes = [("origin", &behind)];
+        let bb_before_aa = |a: &CommitId, d: &CommitId| Ok(*a == behind_id() && *d == local_id());
+
+        let ahead = remote_sync_state(&local, &remotes, None, bb_before_aa).unwrap();
+        assert_eq!(ahead, (true, false, true));
+
+        // The remote moved on without us: out of sync, nothing to push.
+        let remotes = [("origin", &local)];
+        let behind_remote = remote_sync_state(&behind, &remotes, None, bb_before_aa).unwrap();
+        assert_eq!(behind_remote, (true, false, false));
+    }
+
+    #[test]
+    fn config_fingerprint_tracks_repo_config() {
+        let root = temp_repo("config");
+        let before = config_fingerprint(&root, &[]);
+
+        fs::write(root.join(".jj/repo/config.toml"), "[ui]\n").unwrap();
+        let after = config_fingerprint(&root, &[]);
+        assert_ne!(before, after);
+        assert_eq!(after, config_fingerprint(&root, &[]));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_jj_dir_is_found() {
+        let target = temp_repo("symlink-target");
+        let root = env::temp_dir().join(format!("nu_plugin_jj-symlink-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src")).unwrap();
+        std::os::unix::fs::symlink(target.join(".jj"), root.join(".jj")).unwrap();
+
+        let search = RootSearch::default();
+        assert_eq!(
+            find_repo_root(&root.join("src"), &search),
+            Some(root.clone())
+        );
+
+        // Everything lives in one temp dir, so the device check passes.
+        let search = RootSearch {
+            same_filesystem: true,
+            ..RootSearch::default()
+        };
+        assert_eq!(
+            find_repo_root(&root.join("src"), &search),
+            Some(root.clone())
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+        fs::remove_dir_all(&target).unwrap();
+    }
+
+    #[test]
+    fn root_search_depth_bounds_the_walk() {
+        let root = temp_repo("depth");
+        let nested = root.join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+
+        let search = |max_depth| RootSearch {
+            max_depth: Some(max_depth),
+            ..RootSearch::default()
+        };
+        assert_eq!(find_repo_root(&nested, &search(2)), Some(root.clone()));
+        assert_eq!(find_repo_root(&nested, &search(1)), None);
+        assert_eq!(find_repo_root(&root, &search(0)), Some(root.clone()));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn description_only_makes_nonempty_when_asked() {
+        assert!(reports_empty(true, "wip\n", false));
+        assert!(!reports_empty(true, "wip\n", true));
+        assert!(reports_empty(true, "  \n", true));
+        assert!(!reports_empty(false, "", false));
+    }
+
+    #[test]
+    fn explicit_repository_skips_the_search() {
+        let root = temp_repo("explicit");
+        let elsewhere = env::temp_dir();
+
+        let search = |repository: PathBuf| RootSearch {
+            repository: Some(repository),
+            ..RootSearch::default()
+        };
+        assert_eq!(
+            find_repo_root(&elsewhere, &search(root.join(".jj"))),
+            Some(root.clone())
+        );
+        assert_eq!(
+            find_repo_root(&elsewhere, &search(root.clone())),
+            Some(root.clone())
+        );
+        assert_eq!(find_repo_root(&root, &search(root.join("missing"))), None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn missing_repo_is_not_a_repository() {
+        let dir = env::temp_dir().join(format!("nu_plugin_jj-norepo-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let result = collect(
+            &dir,
+            &CollectOptions::default(),
+            &SettingsCache::without_user_config(),
+            &PrefixCache::default(),
+        );
+        assert!(matches!(result, Err(Error::NotARepository)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -1,3 +1,5 @@
+pub mod cache;
+pub mod config;
 pub mod error;
 pub mod jj;
 pub mod plugin;
@@ -1,11 +1,22 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 use nu_plugin::{EngineInterface, EvaluatedCall, Plugin, SimplePluginCommand};
 use nu_protocol::{Category, LabeledError, Signature, SyntaxShape, Type, Value, record};
 
+use crate::cache::{FormatConfigCache, PrefixCache, SettingsCache, StatusCache};
+use crate::config::{FormatConfig, PLATFORM_CONFIG_VAR, format_config_path};
+use crate::error::Error;
 use crate::jj;
 
-pub struct JjPlugin;
+#[derive(Default)]
+pub struct JjPlugin {
+    status_cache: Arc<StatusCache>,
+    settings_cache: Arc<SettingsCache>,
+    prefix_cache: Arc<PrefixCache>,
+    format_config_cache: FormatConfigCache,
+}
 
 impl Plugin for JjPlugin {
     fn version(&self) -> String {
@@ -13,12 +24,36 @@ impl Plugin for JjPlugin {
     }
 
     fn commands(&self) -> Vec<Box<dyn nu_plugin::PluginCommand<Plugin = Self>>> {
-        vec![Box::new(JjPromptCommand), Box::new(JjPromptFormatCommand)]
+        vec![
+            Box::new(JjPromptCommand),
+            Box::new(JjPromptFormatCommand),
+            Box::new(JjLogCommand),
+            Box::new(JjBookmarksCommand),
+            Box::new(JjBookmarkDistanceCommand),
+            Box::new(JjOpLogCommand),
+            Box::new(JjWorkspacesCommand),
+            Box::new(JjStatusCommand),
+            Box::new(JjDiffCommand),
+            Box::new(JjDescribeCommand),
+            Box::new(JjResolveCommand),
+            Box::new(JjConflictsCommand),
+            Box::new(JjRootCommand),
+            Box::new(JjVersionCommand),
+        ]
     }
 }
 
 fn resolve_path(engine: &EngineInterface, call: &EvaluatedCall) -> Result<String, LabeledError> {
-    match call.opt::<String>(0)? {
+    resolve_path_at(engine, call, 0)
+}
+
+/// Like `resolve_path`, for commands whose path follows other positionals.
+fn resolve_path_at(
+    engine: &EngineInterface,
+    call: &EvaluatedCall,
+    pos: usize,
+) -> Result<String, LabeledError> {
+    match call.opt::<String>(pos)? {
         Some(p) => Ok(p),
         None => engine
             .get_current_dir()
@@ -26,6 +61,201 @@ fn resolve_path(engine: &EngineInterface, call: &EvaluatedCall) -> Result<String
     }
 }
 
+/// Environment variable that turns both prompt commands off.
+const DISABLE_ENV_VAR: &str = "NU_PLUGIN_JJ_DISABLE";
+
+/// Whether `NU_PLUGIN_JJ_DISABLE` is set in the caller's Nushell environment
+/// to something other than empty, `0`, or `false`.
+fn prompt_disabled(engine: &EngineInterface) -> Result<bool, LabeledError> {
+    let Some(value) = engine.get_env_var(DISABLE_ENV_VAR)? else {
+        return Ok(false);
+    };
+    Ok(is_disable_value(&value.coerce_string().unwrap_or_default()))
+}
+
+/// The shell's home directory from `HOME`, or `USERPROFILE` on Windows.
+fn home_dir(engine: &EngineInterface) -> Result<Option<PathBuf>, LabeledError> {
+    for name in ["HOME", "USERPROFILE"] {
+        if let Some(value) = engine.get_env_var(name)?
+            && let Ok(home) = value.coerce_string()
+            && !home.is_empty()
+        {
+            return Ok(Some(PathBuf::from(home)));
+        }
+    }
+    Ok(None)
+}
+
+fn is_disable_value(value: &str) -> bool {
+    !matches!(value.trim(), "" | "0" | "false")
+}
+
+/// Adds the flags shared by both prompt commands that feed `jj::CollectOptions`.
+fn collect_flags(signature: Signature) -> Signature {
+    signature
+        .named(
+            "trunk-bookmark",
+            SyntaxShape::List(Box::new(SyntaxShape::String)),
+            "Trunk bookmark names (default: [main master trunk])",
+            None,
+        )
+        .named(
+            "trunk-remote",
+            SyntaxShape::List(Box::new(SyntaxShape::String)),
+            "Trunk remote names (default: [origin upstream])",
+            None,
+        )
+        .named(
+            "bookmark-depth",
+            SyntaxShape::Int,
+            "Ancestor depth to search for bookmarks (default: 10)",
+            None,
+        )
+        .named(
+            "root-search-depth",
+            SyntaxShape::Int,
+            "Most parent directories searched for .jj (default: unlimited)",
+            None,
+        )
+        .named(
+            "repository",
+            SyntaxShape::Filepath,
+            "A .jj directory (or its workspace) to read instead of searching from the path",
+            None,
+        )
+        .switch(
+            "same-filesystem",
+            "Stop the repo root search at filesystem boundaries",
+            None,
+        )
+        .switch(
+            "ignore-bookmark-case",
+            "Match remote bookmarks to local ones case-insensitively",
+            None,
+        )
+        .switch(
+            "no-remote",
+            "Skip remote sync checks for a faster prompt",
+            None,
+        )
+        .switch(
+            "include-git-remote",
+            "Count the git remote of a colocated repo in sync state and immutable heads",
+            None,
+        )
+        .switch(
+            "line-stats",
+            "Read changed files to count lines_added and lines_removed",
+            None,
+        )
+        .switch(
+            "verify-signatures",
+            "Verify the working copy commit's signature for signature_status",
+            None,
+        )
+        .switch(
+            "describe-is-nonempty",
+            "Report a commit with a description as non-empty even if its tree is unchanged",
+            None,
+        )
+        .switch(
+            "nearest-bookmark-only",
+            "Skip the ancestor bookmark search when the working copy has bookmarks",
+            None,
+        )
+        .named(
+            "remote",
+            SyntaxShape::String,
+            "Remote that bookmark sync state is compared against (default: first non-git remote)",
+            None,
+        )
+        .named(
+            "timeout-ms",
+            SyntaxShape::Int,
+            "Give up and return nothing after this many milliseconds (default: no timeout)",
+            None,
+        )
+        .switch(
+            "strict",
+            "Return errors instead of nothing when the repo can't be read",
+            None,
+        )
+        .named(
+            "workspace",
+            SyntaxShape::String,
+            "Workspace whose working-copy commit to read (default: current)",
+            None,
+        )
+        .named(
+            "revision",
+            SyntaxShape::String,
+            "Revset naming a single commit to read instead of the working copy (default: @)",
+            None,
+        )
+}
+
+/// Decides what a prompt command does with a collect result: "not a repo" and
+/// timeouts are always `None`, other failures are `None` unless `strict` is
+/// set. Invalid `--workspace` names always error since they're caller mistakes.
+fn prompt_status(
+    result: Result<jj::JjStatus, Error>,
+    strict: bool,
+) -> Result<Option<jj::JjStatus>, LabeledError> {
+    match result {
+        Ok(status) => Ok(Some(status)),
+        Err(Error::NotARepository | Error::Timeout(_)) => Ok(None),
+        Err(e @ Error::UnknownWorkspace(_)) => Err(LabeledError::new(e.to_string())),
+        Err(e) if strict => Err(LabeledError::new(e.to_string())),
+        Err(_) => Ok(None),
+    }
+}
+
+fn parse_collect_options(
+    engine: &EngineInterface,
+    call: &EvaluatedCall,
+) -> Result<jj::CollectOptions, LabeledError> {
+    let mut options = jj::CollectOptions::default();
+    if let Some(bookmarks) = call.get_flag::<Vec<String>>("trunk-bookmark")? {
+        options.trunk_bookmarks = bookmarks;
+    }
+    if let Some(remotes) = call.get_flag::<Vec<String>>("trunk-remote")? {
+        options.trunk_remotes = remotes;
+    }
+    if let Some(depth) = call.get_flag::<i64>("bookmark-depth")? {
+        options.bookmark_depth = parse_non_negative_usize("bookmark-depth", depth)?;
+    }
+    options.nearest_bookmark_only = call.has_flag("nearest-bookmark-only")?;
+    options.ignore_bookmark_case = call.has_flag("ignore-bookmark-case")?;
+    options.line_stats = call.has_flag("line-stats")?;
+    options.verify_signatures = call.has_flag("verify-signatures")?;
+    options.describe_is_nonempty = call.has_flag("describe-is-nonempty")?;
+    options.include_git_remote = call.has_flag("include-git-remote")?;
+    options.no_remote = call.has_flag("no-remote")?;
+    options.remote = call.get_flag::<String>("remote")?;
+    options.root_search.same_filesystem = call.has_flag("same-filesystem")?;
+    if let Some(repository) = call.get_flag::<String>("repository")? {
+        let cwd = engine
+            .get_current_dir()
+            .map_err(|e| LabeledError::new(format!("get cwd: {e}")))?;
+        options.root_search.repository = Some(Path::new(&cwd).join(repository));
+    }
+    options.root_search.max_depth = call
+        .get_flag::<i64>("root-search-depth")?
+        .map(|depth| parse_non_negative_usize("root-search-depth", depth))
+        .transpose()?;
+    options.workspace = call.get_flag::<String>("workspace")?;
+    options.revision = call.get_flag::<String>("revision")?;
+    Ok(options)
+}
+
+fn parse_timeout(call: &EvaluatedCall) -> Result<Option<Duration>, LabeledError> {
+    let Some(ms) = call.get_flag::<i64>("timeout-ms")? else {
+        return Ok(None);
+    };
+    let ms = parse_non_negative_usize("timeout-ms", ms)?;
+    Ok(Some(Duration::from_millis(ms as u64)))
+}
+
 fn parse_non_negative_usize(name: &str, value: i64) -> Result<usize, LabeledError> {
     if value < 0 {
         return Err(LabeledError::new(format!("--{name} must be non-negative")));
@@ -33,41 +263,74 @@ fn parse_non_negative_usize(name: &str, value: i64) -> Result<usize, LabeledErro
     usize::try_from(value).map_err(|_| LabeledError::new(format!("--{name} is too large")))
 }
 
+/// Parses an indexed 256-color value written as `color123` or `c123`.
+fn parse_color_index(base: &str) -> Option<u8> {
+    let digits = base
+        .strip_prefix("color")
+        .or_else(|| base.strip_prefix('c'))?;
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+fn push_unique<'a>(codes: &mut Vec<&'a str>, code: &'a str) {
+    if !codes.contains(&code) {
+        codes.push(code);
+    }
+}
+
+fn sgr_prefix(codes: &[&str]) -> String {
+    if codes.is_empty() {
+        String::new()
+    } else {
+        format!("{};", codes.join(";"))
+    }
+}
+
 fn color_to_ansi(color: &str) -> String {
-    let parts: Vec<&str> = color.splitn(2, '_').collect();
-
-    let (attrs, base) = match parts.as_slice() {
-        [attr, base] => {
-            let a = match *attr {
-                "bold" => "1;",
-                "dim" => "2;",
-                "italic" => "3;",
-                "underline" => "4;",
-                "bright" => "9",
-                _ => return "\x1b[35m".to_string(),
-            };
-            (a, *base)
-        }
-        [base] => ("", *base),
-        _ => return "\x1b[35m".to_string(),
-    };
+    let mut parts: Vec<&str> = color.split('_').collect();
+    let base = parts.pop().unwrap_or_default();
+
+    // `bright` picks the bright palette for named colors; hex and indexed
+    // colors have no bright variant, so it becomes bold there instead.
+    let mut bright = false;
+    let mut codes: Vec<&str> = Vec::new();
+    let mut extended_codes: Vec<&str> = Vec::new();
+    for attr in parts {
+        let code = match attr {
+            "bold" => "1",
+            "dim" => "2",
+            "italic" => "3",
+            "underline" => "4",
+            "blink" => "5",
+            "reverse" => "7",
+            "strike" => "9",
+            "bright" => {
+                bright = true;
+                push_unique(&mut extended_codes, "1");
+                continue;
+            }
+            _ => return "\x1b[35m".to_string(),
+        };
+        push_unique(&mut codes, code);
+        push_unique(&mut extended_codes, code);
+    }
 
     if base.starts_with('#') && base.len() == 7 {
         let r = u8::from_str_radix(&base[1..3], 16).unwrap_or(0);
         let g = u8::from_str_radix(&base[3..5], 16).unwrap_or(0);
         let b = u8::from_str_radix(&base[5..7], 16).unwrap_or(0);
-        let attr_code = match attrs {
-            "1;" => "1;",
-            "2;" => "2;",
-            "3;" => "3;",
-            "4;" => "4;",
-            "9" => "1;",
-            _ => "",
-        };
-        return format!("\x1b[{attr_code}38;2;{r};{g};{b}m");
+        let prefix = sgr_prefix(&extended_codes);
+        return format!("\x1b[{prefix}38;2;{r};{g};{b}m");
+    }
+
+    if let Some(index) = parse_color_index(base) {
+        let prefix = sgr_prefix(&extended_codes);
+        return format!("\x1b[{prefix}38;5;{index}m");
     }
 
-    let fg = if attrs == "9" {
+    let fg = if bright {
         match base {
             "black" => "90",
             "red" => "91",
@@ -93,11 +356,41 @@ fn color_to_ansi(color: &str) -> String {
         }
     };
 
-    if attrs == "9" || attrs.is_empty() {
-        format!("\x1b[{fg}m")
-    } else {
-        format!("\x1b[{attrs}{fg}m")
+    format!("\x1b[{}{fg}m", sgr_prefix(&codes))
+}
+
+/// Background counterpart to `color_to_ansi`. Accepts the same base colors
+/// with an optional `bright_` prefix; unrecognized values emit no background.
+fn bg_color_to_ansi(color: &str) -> String {
+    let (bright, base) = match color.strip_prefix("bright_") {
+        Some(base) => (true, base),
+        None => (false, color),
+    };
+
+    if base.starts_with('#') && base.len() == 7 {
+        let r = u8::from_str_radix(&base[1..3], 16).unwrap_or(0);
+        let g = u8::from_str_radix(&base[3..5], 16).unwrap_or(0);
+        let b = u8::from_str_radix(&base[5..7], 16).unwrap_or(0);
+        return format!("\x1b[48;2;{r};{g};{b}m");
+    }
+
+    if let Some(index) = parse_color_index(base) {
+        return format!("\x1b[48;5;{index}m");
     }
+
+    let offset = match base {
+        "black" => 0,
+        "red" => 1,
+        "green" => 2,
+        "yellow" => 3,
+        "blue" => 4,
+        "magenta" => 5,
+        "cyan" => 6,
+        "white" => 7,
+        _ => return String::new(),
+    };
+    let bg = if bright { 100 + offset } else { 40 + offset };
+    format!("\x1b[{bg}m")
 }
 
 struct JjPromptCommand;
@@ -114,30 +407,46 @@ impl SimplePluginCommand for JjPromptCommand {
     }
 
     fn signature(&self) -> Signature {
-        Signature::build("jj-prompt")
+        collect_flags(Signature::build("jj-prompt"))
             .optional(
                 "path",
                 SyntaxShape::Filepath,
                 "Path to check (defaults to PWD)",
             )
+            .switch(
+                "debug",
+                "Add a diagnostics record with timings and counters",
+                None,
+            )
             .input_output_types(vec![(Type::Nothing, Type::Any)])
             .category(Category::Custom("prompt".into()))
     }
 
     fn run(
         &self,
-        _plugin: &JjPlugin,
+        plugin: &JjPlugin,
         engine: &EngineInterface,
         call: &EvaluatedCall,
         _input: &Value,
     ) -> Result<Value, LabeledError> {
         let span = call.head;
+        if prompt_disabled(engine)? {
+            return Ok(Value::nothing(span));
+        }
         let path_str = resolve_path(engine, call)?;
         let path = Path::new(&path_str);
 
-        let status = match jj::collect(path) {
-            Ok(Some(s)) => s,
-            Ok(None) | Err(_) => return Ok(Value::nothing(span)),
+        let collect_options = parse_collect_options(engine, call)?;
+        let timeout = parse_timeout(call)?;
+        let result = plugin.status_cache.collect_within(
+            path,
+            &collect_options,
+            &plugin.settings_cache,
+            &plugin.prefix_cache,
+            timeout,
+        );
+        let Some(status) = prompt_status(result, call.has_flag("strict")?)? else {
+            return Ok(Value::nothing(span));
         };
 
         let bookmarks_val: Vec<Value> = status
@@ -148,29 +457,132 @@ impl SimplePluginCommand for JjPromptCommand {
                     record! {
                         "name" => Value::string(&b.name, span),
                         "distance" => Value::int(b.distance as i64, span),
+                        "target" => Value::string(&b.target, span),
+                        "is_tracked" => Value::bool(b.is_tracked, span),
+                        "has_remote" => Value::bool(b.has_remote, span),
+                        "is_synced" => Value::bool(b.is_synced, span),
+                        "needs_push" => Value::bool(b.needs_push, span),
                     },
                     span,
                 )
             })
             .collect();
 
-        Ok(Value::record(
-            record! {
-                "repo_root" => Value::string(&status.repo_root, span),
-                "change_id" => Value::string(&status.change_id, span),
-                "change_id_prefix_len" => Value::int(status.change_id_prefix_len as i64, span),
-                "bookmarks" => Value::list(bookmarks_val, span),
-                "description" => Value::string(&status.description, span),
-                "empty" => Value::bool(status.empty, span),
-                "conflict" => Value::bool(status.conflict, span),
-                "divergent" => Value::bool(status.divergent, span),
-                "hidden" => Value::bool(status.hidden, span),
-                "immutable" => Value::bool(status.immutable, span),
-                "has_remote" => Value::bool(status.has_remote, span),
-                "is_synced" => Value::bool(status.is_synced, span),
-            },
-            span,
-        ))
+        // Display-only, so it's derived here rather than keyed into the cache.
+        let repo_root_display =
+            jj::abbreviate_home(Path::new(&status.repo_root), home_dir(engine)?.as_deref());
+
+        let mut record = record! {
+            "repo_root" => Value::string(&status.repo_root, span),
+            "repo_root_display" => Value::string(&repo_root_display, span),
+            "operation_id" => Value::string(&status.operation_id, span),
+            "colocated" => Value::bool(status.colocated, span),
+            "degraded" => Value::bool(status.degraded, span),
+            "change_id" => Value::string(&status.change_id, span),
+            "change_id_full" => Value::string(&status.change_id_full, span),
+            "change_id_prefix_len" => Value::int(status.change_id_prefix_len as i64, span),
+            "short_id_len" => Value::int(status.short_id_len as i64, span),
+            "commit_id" => Value::string(&status.commit_id, span),
+            "commit_id_prefix_len" => Value::int(status.commit_id_prefix_len as i64, span),
+            "parents" => Value::list(
+                status
+                    .parents
+                    .iter()
+                    .map(|id| Value::string(id, span))
+                    .collect(),
+                span,
+            ),
+            "is_merge" => Value::bool(status.is_merge, span),
+            "is_root" => Value::bool(status.is_root, span),
+            "bookmarks" => Value::list(bookmarks_val, span),
+            "description" => Value::string(&status.description, span),
+            "description_full" => Value::string(&status.description_full, span),
+            "author_name" => Value::string(&status.author_name, span),
+            "author_email" => Value::string(&status.author_email, span),
+            "authored_by_me" => Value::bool(status.authored_by_me, span),
+            "committer_email" => Value::string(&status.committer_email, span),
+            "authored_at" => Value::date(status.authored_at, span),
+            "age_seconds" => Value::int(status.age_seconds, span),
+            "age_human" => Value::string(&status.age_human, span),
+            "empty" => Value::bool(status.empty, span),
+            "files_added" => Value::int(status.files_added as i64, span),
+            "files_modified" => Value::int(status.files_modified as i64, span),
+            "files_deleted" => Value::int(status.files_deleted as i64, span),
+            "lines_added" => Value::int(status.lines_added as i64, span),
+            "lines_removed" => Value::int(status.lines_removed as i64, span),
+            "new_files" => Value::int(status.new_files as i64, span),
+            "dirty" => Value::bool(status.dirty, span),
+            "conflict" => Value::bool(status.conflict, span),
+            "conflict_count" => Value::int(status.conflict_count as i64, span),
+            "conflicted_files" => Value::list(
+                status
+                    .conflicted_files
+                    .iter()
+                    .map(|path| Value::string(path, span))
+                    .collect(),
+                span,
+            ),
+            "conflicts" => Value::list(
+                status
+                    .conflicts
+                    .iter()
+                    .map(|c| {
+                        Value::record(
+                            record! {
+                                "path" => Value::string(&c.path, span),
+                                "num_sides" => Value::int(c.num_sides as i64, span),
+                            },
+                            span,
+                        )
+                    })
+                    .collect(),
+                span,
+            ),
+            "divergent" => Value::bool(status.divergent, span),
+            "divergent_count" => Value::int(status.divergent_count as i64, span),
+            "hidden" => Value::bool(status.hidden, span),
+            "hidden_reason" => status
+                .hidden_reason
+                .as_ref()
+                .map_or_else(|| Value::nothing(span), |r| Value::string(r, span)),
+            "immutable" => Value::bool(status.immutable, span),
+            "is_stale" => Value::bool(status.is_stale, span),
+            "git_head_behind" => Value::bool(status.git_head_behind, span),
+            "signed" => Value::bool(status.signed, span),
+            "signature_status" => Value::string(status.signature_status.as_str(), span),
+            "has_remote" => Value::bool(status.has_remote, span),
+            "is_synced" => Value::bool(status.is_synced, span),
+            "needs_push" => Value::bool(status.needs_push, span),
+            "has_trunk" => Value::bool(status.has_trunk, span),
+            "on_trunk" => Value::bool(status.on_trunk, span),
+            "ahead" => Value::int(status.ahead as i64, span),
+            "behind" => Value::int(status.behind as i64, span),
+            "descendant_count" => Value::int(status.descendant_count as i64, span),
+        };
+
+        if let Some(path_in_repo) = &status.path_in_repo {
+            record.push("path_in_repo", Value::string(path_in_repo, span));
+        }
+
+        if call.has_flag("debug")? {
+            let diagnostics = &status.diagnostics;
+            record.push(
+                "diagnostics",
+                Value::record(
+                    record! {
+                        "load_ms" => Value::float(diagnostics.load_ms, span),
+                        "ancestor_walk_ms" => Value::float(diagnostics.ancestor_walk_ms, span),
+                        "commits_visited" => Value::int(diagnostics.commits_visited as i64, span),
+                        "total_ms" => Value::float(diagnostics.total_ms, span),
+                        "cached" => Value::bool(diagnostics.cached, span),
+                        "repo_root" => Value::string(&status.repo_root, span),
+                    },
+                    span,
+                ),
+            );
+        }
+
+        Ok(Value::record(record, span))
     }
 }
 
@@ -178,88 +590,853 @@ struct JjPromptFormatCommand;
 
 const ANSI_RESET: &str = "\x1b[0m";
 
+#[derive(Clone)]
 struct FormatOptions {
     icon: String,
+    /// State-specific icons, picked over `icon` in the order conflict, merge,
+    /// empty.
+    icon_conflict: Option<String>,
+    icon_merge: Option<String>,
+    icon_empty: Option<String>,
     icon_color: String,
+    icon_bg: Option<String>,
     change_id_color: String,
     change_id_rest_color: String,
+    /// Change ID prefix color once `age_seconds` exceeds `stale_after_secs`.
+    stale_color: String,
+    stale_after_secs: Option<usize>,
     bookmark_color: String,
+    divergence_color: String,
     status_color: String,
+    status_bg: Option<String>,
     conflict: String,
     divergent: String,
     hidden: String,
     immutable: String,
+    /// Per-flag colors; the symbol is emitted uncolored when `None`.
+    conflict_color: Option<String>,
+    divergent_color: Option<String>,
+    hidden_color: Option<String>,
+    immutable_color: Option<String>,
+    merge: String,
+    stale: String,
+    git_head: String,
+    push: String,
+    signed: String,
+    dirty: String,
+    ahead_symbol: String,
+    behind_symbol: String,
+    descendant_symbol: String,
+    /// Appended to bookmarks that have a remote, by whether they match it.
+    synced_symbol: String,
+    unsynced_symbol: String,
+    show_descendants: bool,
     change_id_len: usize,
     empty_text: String,
     no_desc_text: String,
+    root_text: String,
+    degraded_text: String,
     desc_len: usize,
+    ellipsis: String,
+    truncate_words: bool,
+    /// Render every description line, joined with spaces, not just the first.
+    full_description: bool,
+    template: Option<String>,
+    no_color: bool,
+    json: bool,
+    show_bookmark_distance: bool,
+    max_bookmarks: Option<usize>,
+    link_root: bool,
+    no_icon: bool,
+    no_bookmarks: bool,
+    no_flags: bool,
+    no_description: bool,
+    reverse: bool,
+    /// Put the bookmarks and flags segments on a second line.
+    multiline: bool,
+    prefix: String,
+    suffix: String,
+    separator: String,
+    powerline: bool,
 }
 
-fn format_prompt(status: &jj::JjStatus, options: &FormatOptions) -> String {
-    let icon_color = color_to_ansi(&options.icon_color);
-    let cid_color = color_to_ansi(&options.change_id_color);
-    let cid_rest_color = color_to_ansi(&options.change_id_rest_color);
-    let bm_color = color_to_ansi(&options.bookmark_color);
-    let status_color = color_to_ansi(&options.status_color);
-
-    let mut parts: Vec<String> = Vec::new();
-
-    parts.push(format!("{icon_color}{}{ANSI_RESET}", options.icon));
-
-    let cid = &status.change_id[..options.change_id_len.min(status.change_id.len())];
-    let prefix_len = status.change_id_prefix_len.min(cid.len());
-    let cid_prefix = &cid[..prefix_len];
-    let cid_rest = &cid[prefix_len..];
-    parts.push(format!(
-        "{cid_color}{cid_prefix}{ANSI_RESET}{cid_rest_color}{cid_rest}{ANSI_RESET}"
-    ));
-
-    let bookmarks: Vec<String> = status
-        .bookmarks
+/// Loads `nu_plugin_jj.toml` from nushell's default config directory,
+/// resolved with the shell's `XDG_CONFIG_HOME`.
+fn load_format_config(
+    engine: &EngineInterface,
+    cache: &FormatConfigCache,
+) -> Result<FormatConfig, LabeledError> {
+    let env_var = |name: &str| -> Result<Option<String>, LabeledError> {
+        Ok(engine
+            .get_env_var(name)?
+            .and_then(|value| value.coerce_string().ok()))
+    };
+    let xdg_config_home = env_var("XDG_CONFIG_HOME")?;
+    let platform_base = env_var(PLATFORM_CONFIG_VAR)?;
+    let Some(path) = format_config_path(xdg_config_home.as_deref(), platform_base.as_deref())
+    else {
+        return Ok(FormatConfig::default());
+    };
+    cache
+        .get(&path)
+        .map_err(|e| LabeledError::new(e.to_string()))
+}
+
+/// A switch the config file can also set. Passing it, bare or as
+/// `--name=false`, wins over the file.
+fn switch_or(call: &EvaluatedCall, name: &str, config: bool) -> Result<bool, LabeledError> {
+    if call.named.iter().any(|(flag, _)| flag.item == name) {
+        Ok(call.has_flag(name)?)
+    } else {
+        Ok(config)
+    }
+}
+
+/// Builds `jj-prompt format`'s options from its flags, falling back to the
+/// config file and then the defaults. `short_id_len` is the change ID length
+/// used when neither sets `change-id-len`.
+fn format_options(
+    call: &EvaluatedCall,
+    config: FormatConfig,
+    short_id_len: usize,
+) -> Result<FormatOptions, LabeledError> {
+    let theme = match call.get_flag::<String>("theme")?.or(config.theme) {
+        Some(name) => find_theme(&name)?,
+        None => &DEFAULT_THEME,
+    };
+    let symbols = if switch_or(call, "nerd-fonts", config.nerd_fonts)? {
+        &NERD_FONT_SYMBOLS
+    } else {
+        &EMOJI_SYMBOLS
+    };
+
+    let powerline = switch_or(call, "powerline", config.powerline)?;
+    let separator = call.get_flag::<String>("separator")?.or(config.separator);
+    if powerline && separator.is_some() {
+        return Err(LabeledError::new(
+            "--separator can't be combined with --powerline, which joins segments with arrows",
+        ));
+    }
+
+    Ok(FormatOptions {
+        icon: call
+            .get_flag::<String>("icon")?
+            .or(config.icon)
+            .unwrap_or_else(|| "󱗆".to_string()),
+        icon_conflict: call
+            .get_flag::<String>("icon-conflict")?
+            .or(config.icon_conflict),
+        icon_merge: call.get_flag::<String>("icon-merge")?.or(config.icon_merge),
+        icon_empty: call.get_flag::<String>("icon-empty")?.or(config.icon_empty),
+        icon_color: call
+            .get_flag::<String>("icon-color")?
+            .or(config.icon_color)
+            .unwrap_or_else(|| theme.icon.to_string()),
+        icon_bg: call.get_flag::<String>("icon-bg")?.or(config.icon_bg),
+        change_id_color: call
+            .get_flag::<String>("change-id-color")?
+            .or(config.change_id_color)
+            .unwrap_or_else(|| theme.change_id.to_string()),
+        change_id_rest_color: call
+            .get_flag::<String>("change-id-rest-color")?
+            .or(config.change_id_rest_color)
+            .unwrap_or_else(|| theme.change_id_rest.to_string()),
+        stale_color: call
+            .get_flag::<String>("stale-color")?
+            .or(config.stale_color)
+            .unwrap_or_else(|| "red".to_string()),
+        stale_after_secs: call
+            .get_flag::<i64>("stale-after-secs")?
+            .or(config.stale_after_secs)
+            .map(|secs| parse_non_negative_usize("stale-after-secs", secs))
+            .transpose()?,
+        bookmark_color: call
+            .get_flag::<String>("bookmark-color")?
+            .or(config.bookmark_color)
+            .unwrap_or_else(|| theme.bookmark.to_string()),
+        divergence_color: call
+            .get_flag::<String>("divergence-color")?
+            .or(config.divergence_color)
+            .unwrap_or_else(|| theme.divergence.to_string()),
+        status_color: call
+            .get_flag::<String>("status-color")?
+            .or(config.status_color)
+            .unwrap_or_else(|| theme.status.to_string()),
+        status_bg: call.get_flag::<String>("status-bg")?.or(config.status_bg),
+        conflict: call
+            .get_flag::<String>("conflict")?
+            .or(config.conflict)
+            .unwrap_or_else(|| symbols.conflict.to_string()),
+        divergent: call
+            .get_flag::<String>("divergent")?
+            .or(config.divergent)
+            .unwrap_or_else(|| symbols.divergent.to_string()),
+        hidden: call
+            .get_flag::<String>("hidden")?
+            .or(config.hidden)
+            .unwrap_or_else(|| symbols.hidden.to_string()),
+        immutable: call
+            .get_flag::<String>("immutable")?
+            .or(config.immutable)
+            .unwrap_or_else(|| symbols.immutable.to_string()),
+        conflict_color: call
+            .get_flag::<String>("conflict-color")?
+            .or(config.conflict_color),
+        divergent_color: call
+            .get_flag::<String>("divergent-color")?
+            .or(config.divergent_color),
+        hidden_color: call
+            .get_flag::<String>("hidden-color")?
+            .or(config.hidden_color),
+        immutable_color: call
+            .get_flag::<String>("immutable-color")?
+            .or(config.immutable_color),
+        merge: call
+            .get_flag::<String>("merge-symbol")?
+            .or(config.merge_symbol)
+            .unwrap_or_else(|| symbols.merge.to_string()),
+        stale: call
+            .get_flag::<String>("stale")?
+            .or(config.stale)
+            .unwrap_or_else(|| symbols.stale.to_string()),
+        git_head: call
+            .get_flag::<String>("git-head-symbol")?
+            .or(config.git_head_symbol)
+            .unwrap_or_else(|| symbols.git_head.to_string()),
+        signed: call
+            .get_flag::<String>("signed-symbol")?
+            .or(config.signed_symbol)
+            .unwrap_or_else(|| symbols.signed.to_string()),
+        dirty: call
+            .get_flag::<String>("dirty-symbol")?
+            .or(config.dirty_symbol)
+            .unwrap_or_else(|| symbols.dirty.to_string()),
+        push: call
+            .get_flag::<String>("push-symbol")?
+            .or(config.push_symbol)
+            .unwrap_or_else(|| symbols.push.to_string()),
+        ahead_symbol: call
+            .get_flag::<String>("ahead-symbol")?
+            .or(config.ahead_symbol)
+            .unwrap_or_else(|| "⇡".to_string()),
+        behind_symbol: call
+            .get_flag::<String>("behind-symbol")?
+            .or(config.behind_symbol)
+            .unwrap_or_else(|| "⇣".to_string()),
+        synced_symbol: call
+            .get_flag::<String>("synced-symbol")?
+            .or(config.synced_symbol)
+            .unwrap_or_default(),
+        unsynced_symbol: call
+            .get_flag::<String>("unsynced-symbol")?
+            .or(config.unsynced_symbol)
+            .unwrap_or_else(|| "*".to_string()),
+        descendant_symbol: call
+            .get_flag::<String>("descendant-symbol")?
+            .or(config.descendant_symbol)
+            .unwrap_or_else(|| "↧".to_string()),
+        show_descendants: switch_or(call, "show-descendants", config.show_descendants)?,
+        change_id_len: match call
+            .get_flag::<i64>("change-id-len")?
+            .or(config.change_id_len)
+        {
+            Some(len) => parse_non_negative_usize("change-id-len", len)?,
+            None => short_id_len,
+        },
+        empty_text: call
+            .get_flag::<String>("empty-text")?
+            .or(config.empty_text)
+            .unwrap_or_else(|| "(empty)".to_string()),
+        no_desc_text: call
+            .get_flag::<String>("no-desc-text")?
+            .or(config.no_desc_text)
+            .unwrap_or_else(|| "(no description set)".to_string()),
+        root_text: call
+            .get_flag::<String>("root-text")?
+            .or(config.root_text)
+            .unwrap_or_else(|| "(root)".to_string()),
+        degraded_text: call
+            .get_flag::<String>("degraded-text")?
+            .or(config.degraded_text)
+            .unwrap_or_else(|| "⚠ working copy missing".to_string()),
+        desc_len: parse_non_negative_usize(
+            "desc-len",
+            call.get_flag::<i64>("desc-len")?
+                .or(config.desc_len)
+                .unwrap_or(29),
+        )?,
+        ellipsis: call
+            .get_flag::<String>("ellipsis")?
+            .or(config.ellipsis)
+            .unwrap_or_else(|| "…".to_string()),
+        truncate_words: switch_or(call, "truncate-words", config.truncate_words)?,
+        full_description: switch_or(call, "full-description", config.full_description)?,
+        template: call.get_flag::<String>("template")?.or(config.template),
+        no_color: switch_or(call, "no-color", config.no_color)?,
+        json: switch_or(call, "json", config.json)?,
+        show_bookmark_distance: switch_or(
+            call,
+            "show-bookmark-distance",
+            config.show_bookmark_distance,
+        )?,
+        max_bookmarks: call
+            .get_flag::<i64>("max-bookmarks")?
+            .or(config.max_bookmarks)
+            .map(|value| parse_non_negative_usize("max-bookmarks", value))
+            .transpose()?,
+        link_root: switch_or(call, "link-root", config.link_root)?,
+        no_icon: switch_or(call, "no-icon", config.no_icon)?,
+        no_bookmarks: switch_or(call, "no-bookmarks", config.no_bookmarks)?,
+        no_flags: switch_or(call, "no-flags", config.no_flags)?,
+        no_description: switch_or(call, "no-description", config.no_description)?,
+        reverse: switch_or(call, "reverse", config.reverse)?,
+        multiline: switch_or(call, "multiline", config.multiline)?,
+        prefix: call
+            .get_flag::<String>("prefix")?
+            .or(config.prefix)
+            .unwrap_or_default(),
+        suffix: call
+            .get_flag::<String>("suffix")?
+            .or(config.suffix)
+            .unwrap_or_default(),
+        separator: separator.unwrap_or_else(|| " ".to_string()),
+        powerline,
+    })
+}
+
+/// The first `chars` characters of `text` (all of it when shorter), cut on a
+/// char boundary.
+fn char_prefix(text: &str, chars: usize) -> &str {
+    match text.char_indices().nth(chars) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
+
+/// The change ID cut to `len` characters, split into its unique prefix and
+/// the rest. `prefix_len` is clamped to the shown length, so the rest is
+/// empty rather than out of range when the prefix is longer.
+fn split_change_id(change_id: &str, len: usize, prefix_len: usize) -> (&str, &str) {
+    let shown = char_prefix(change_id, len);
+    let prefix = char_prefix(shown, prefix_len.min(len));
+    (prefix, &shown[prefix.len()..])
+}
+
+/// Wraps `text` in an OSC 8 hyperlink to `url`, closing the link afterwards.
+fn osc8_link(url: &str, text: &str) -> String {
+    format!("\x1b]8;;{url}\x1b\\{text}\x1b]8;;\x1b\\")
+}
+
+/// A `file://` URL for an absolute path, percent-encoding bytes that aren't
+/// safe in a URL path.
+fn file_url(path: &str) -> String {
+    let mut url = String::from("file://");
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            url.push(byte as char);
+        } else {
+            url.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    url
+}
+
+/// Colors a `--theme` preset supplies; individual color flags override them.
+struct Theme {
+    icon: &'static str,
+    change_id: &'static str,
+    change_id_rest: &'static str,
+    bookmark: &'static str,
+    divergence: &'static str,
+    status: &'static str,
+}
+
+const DEFAULT_THEME: Theme = Theme {
+    icon: "blue",
+    change_id: "bold_magenta",
+    change_id_rest: "dim_magenta",
+    bookmark: "magenta",
+    divergence: "cyan",
+    status: "green",
+};
+
+const THEMES: &[(&str, Theme)] = &[
+    ("default", DEFAULT_THEME),
+    (
+        "gruvbox",
+        Theme {
+            icon: "#83a598",
+            change_id: "bold_#d3869b",
+            change_id_rest: "dim_#d3869b",
+            bookmark: "#fabd2f",
+            divergence: "#8ec07c",
+            status: "#b8bb26",
+        },
+    ),
+    (
+        "nord",
+        Theme {
+            icon: "#88c0d0",
+            change_id: "bold_#b48ead",
+            change_id_rest: "dim_#b48ead",
+            bookmark: "#81a1c1",
+            divergence: "#8fbcbb",
+            status: "#a3be8c",
+        },
+    ),
+    (
+        "mono",
+        Theme {
+            icon: "white",
+            change_id: "bold_white",
+            change_id_rest: "dim_white",
+            bookmark: "white",
+            divergence: "white",
+            status: "dim_white",
+        },
+    ),
+];
+
+/// Default flag symbols; `--nerd-fonts` swaps the emoji set for Nerd Font
+/// glyphs. Individual symbol flags override either set.
+struct FlagSymbols {
+    conflict: &'static str,
+    divergent: &'static str,
+    hidden: &'static str,
+    immutable: &'static str,
+    merge: &'static str,
+    stale: &'static str,
+    git_head: &'static str,
+    signed: &'static str,
+    dirty: &'static str,
+    push: &'static str,
+}
+
+const EMOJI_SYMBOLS: FlagSymbols = FlagSymbols {
+    conflict: "💥",
+    divergent: "🚧",
+    hidden: "👻",
+    immutable: "🔒",
+    merge: "🪢",
+    stale: "⚠️",
+    git_head: "🔀",
+    signed: "🔏",
+    dirty: "📝",
+    push: "📤",
+};
+
+const NERD_FONT_SYMBOLS: FlagSymbols = FlagSymbols {
+    conflict: "\u{f0e7}",  // nf-fa-bolt
+    divergent: "\u{f126}", // nf-fa-code_fork
+    hidden: "\u{f070}",    // nf-fa-eye_slash
+    immutable: "\u{f023}", // nf-fa-lock
+    merge: "\u{e727}",     // nf-dev-git_merge
+    stale: "\u{f071}",     // nf-fa-warning
+    git_head: "\u{e702}",  // nf-dev-git
+    signed: "\u{f084}",    // nf-fa-key
+    dirty: "\u{f040}",     // nf-fa-pencil
+    push: "\u{f093}",      // nf-fa-upload
+};
+
+fn find_theme(name: &str) -> Result<&'static Theme, LabeledError> {
+    THEMES
+        .iter()
+        .find(|(theme, _)| *theme == name)
+        .map(|(_, theme)| theme)
+        .ok_or_else(|| {
+            let known: Vec<&str> = THEMES.iter().map(|(theme, _)| *theme).collect();
+            LabeledError::new(format!(
+                "unknown --theme '{name}' (expected one of: {})",
+                known.join(", ")
+            ))
+        })
+}
+
+/// Joins the non-blank lines of `text` with single spaces.
+fn join_lines(text: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn truncate_description(description: &str, options: &FormatOptions) -> String {
+    if description.chars().count() <= options.desc_len {
+        return description.to_string();
+    }
+
+    let cut: String = description.chars().take(options.desc_len).collect();
+    let kept = if options.truncate_words {
+        let at_boundary = description
+            .chars()
+            .nth(options.desc_len)
+            .is_some_and(char::is_whitespace);
+        if at_boundary {
+            cut.trim_end()
+        } else {
+            cut.rfind(char::is_whitespace)
+                .map(|i| cut[..i].trim_end())
+                .filter(|words| !words.is_empty())
+                .unwrap_or(&cut)
+        }
+    } else {
+        &cut
+    };
+
+    format!("{kept}{}", options.ellipsis)
+}
+
+/// The prompt's segments in display order. Segments with no text are left
+/// out, so separators never double up.
+fn prompt_segments(status: &jj::JjStatus, options: &FormatOptions) -> Vec<(&'static str, String)> {
+    // Powerline segments are painted by `render_powerline`.
+    let plain = options.no_color || renders_powerline(options);
+    let paint = |color: &str, bg: Option<&str>| -> String {
+        if plain {
+            return String::new();
+        }
+        let bg = bg.map_or_else(String::new, bg_color_to_ansi);
+        format!("{}{bg}", color_to_ansi(color))
+    };
+    let reset = if plain { "" } else { ANSI_RESET };
+
+    let icon_color = paint(&options.icon_color, options.icon_bg.as_deref());
+    let is_old = options
+        .stale_after_secs
+        .is_some_and(|secs| status.age_seconds > secs as i64);
+    let cid_color = if is_old {
+        paint(&options.stale_color, None)
+    } else {
+        paint(&options.change_id_color, None)
+    };
+    let cid_rest_color = paint(&options.change_id_rest_color, None);
+    let bm_color = paint(&options.bookmark_color, None);
+    let div_color = paint(&options.divergence_color, None);
+    let status_color = paint(&options.status_color, options.status_bg.as_deref());
+
+    let mut parts: Vec<(&'static str, String)> = Vec::new();
+
+    let icon = [
+        (status.conflict, &options.icon_conflict),
+        (status.is_merge, &options.icon_merge),
+        (status.empty, &options.icon_empty),
+    ]
+    .into_iter()
+    .find_map(|(active, icon)| icon.as_ref().filter(|_| active))
+    .unwrap_or(&options.icon);
+    if !options.no_icon && !icon.is_empty() {
+        let icon = if options.link_root {
+            osc8_link(&file_url(&status.repo_root), icon)
+        } else {
+            icon.clone()
+        };
+        parts.push(("icon", format!("{icon_color}{icon}{reset}")));
+    }
+
+    if status.degraded {
+        if !options.no_description && !options.degraded_text.is_empty() {
+            parts.push((
+                "description",
+                format!("{status_color}{}{reset}", options.degraded_text),
+            ));
+        }
+        return parts;
+    }
+
+    let (cid_prefix, cid_rest) = split_change_id(
+        &status.change_id_full,
+        options.change_id_len,
+        status.change_id_prefix_len,
+    );
+    if !cid_prefix.is_empty() || !cid_rest.is_empty() {
+        parts.push((
+            "change_id",
+            format!("{cid_color}{cid_prefix}{reset}{cid_rest_color}{cid_rest}{reset}"),
+        ));
+    }
+
+    let shown = options
+        .max_bookmarks
+        .unwrap_or(status.bookmarks.len())
+        .min(status.bookmarks.len());
+    let mut bookmarks: Vec<String> = status.bookmarks[..shown]
         .iter()
-        .map(|b| format!("{bm_color}{}{ANSI_RESET}", b.name))
+        .map(|b| {
+            let sync = match (b.has_remote, b.is_synced) {
+                (false, _) => "",
+                (true, true) => options.synced_symbol.as_str(),
+                (true, false) => options.unsynced_symbol.as_str(),
+            };
+            if options.show_bookmark_distance && b.distance > 0 {
+                format!("{bm_color}{}+{}{sync}{reset}", b.name, b.distance)
+            } else {
+                format!("{bm_color}{}{sync}{reset}", b.name)
+            }
+        })
         .collect();
-    if !bookmarks.is_empty() {
-        parts.push(bookmarks.join(" "));
+    let overflow = status.bookmarks.len() - shown;
+    if overflow > 0 {
+        bookmarks.push(format!("{bm_color}+{overflow}{reset}"));
+    }
+    if !bookmarks.is_empty() && !options.no_bookmarks {
+        parts.push(("bookmarks", bookmarks.join(" ")));
+    }
+
+    let mut divergence = String::new();
+    if status.ahead > 0 {
+        divergence.push_str(&format!("{}{}", options.ahead_symbol, status.ahead));
+    }
+    if status.behind > 0 {
+        divergence.push_str(&format!("{}{}", options.behind_symbol, status.behind));
+    }
+    if options.show_descendants && status.descendant_count > 0 {
+        divergence.push_str(&format!(
+            "{}{}",
+            options.descendant_symbol, status.descendant_count
+        ));
+    }
+    if !divergence.is_empty() {
+        parts.push(("divergence", format!("{div_color}{divergence}{reset}")));
     }
 
+    let colored_flag = |symbol: &str, color: &Option<String>| -> String {
+        match color {
+            Some(color) if !plain => {
+                format!("{}{symbol}{reset}", paint(color, None))
+            }
+            _ => symbol.to_string(),
+        }
+    };
+
     let mut flags = String::new();
     if status.conflict {
-        flags.push_str(&options.conflict);
+        let mut conflict = options.conflict.clone();
+        if status.conflict_count > 0 {
+            conflict.push_str(&status.conflict_count.to_string());
+        }
+        flags.push_str(&colored_flag(&conflict, &options.conflict_color));
     }
     if status.divergent {
-        flags.push_str(&options.divergent);
+        let divergent = format!("{}{}", options.divergent, status.divergent_count);
+        flags.push_str(&colored_flag(&divergent, &options.divergent_color));
     }
     if status.hidden {
-        flags.push_str(&options.hidden);
+        flags.push_str(&colored_flag(&options.hidden, &options.hidden_color));
     }
     if status.immutable {
-        flags.push_str(&options.immutable);
+        flags.push_str(&colored_flag(&options.immutable, &options.immutable_color));
     }
-    if !flags.is_empty() {
-        parts.push(flags);
+    if status.is_merge {
+        flags.push_str(&options.merge);
     }
-
-    if status.empty {
-        parts.push(format!("{status_color}{}{ANSI_RESET}", options.empty_text));
+    if status.is_stale {
+        flags.push_str(&options.stale);
+    }
+    if status.git_head_behind {
+        flags.push_str(&options.git_head);
+    }
+    if status.needs_push {
+        flags.push_str(&options.push);
+    }
+    if status.signature_status == jj::SignatureStatus::Good {
+        flags.push_str(&options.signed);
+    }
+    if status.dirty {
+        flags.push_str(&options.dirty);
+    }
+    if !flags.is_empty() && !options.no_flags {
+        parts.push(("flags", flags));
     }
 
-    if status.description.is_empty() {
-        parts.push(format!(
-            "{status_color}{}{ANSI_RESET}",
-            options.no_desc_text
+    if status.empty && !options.empty_text.is_empty() {
+        parts.push((
+            "empty",
+            format!("{status_color}{}{reset}", options.empty_text),
         ));
-    } else {
-        let truncated = if status.description.chars().count() > options.desc_len {
-            let s: String = status.description.chars().take(options.desc_len).collect();
-            format!("{s}…")
+    }
+
+    if !options.no_description {
+        let text = if options.full_description {
+            join_lines(&status.description_full)
+        } else {
+            status.description.clone()
+        };
+        let description = if status.is_root && text.is_empty() {
+            options.root_text.clone()
+        } else if text.is_empty() {
+            options.no_desc_text.clone()
         } else {
-            status.description.to_string()
+            truncate_description(&text, options)
         };
-        parts.push(format!("{status_color}{truncated}{ANSI_RESET}"));
+        if !description.is_empty() {
+            parts.push(("description", format!("{status_color}{description}{reset}")));
+        }
+    }
+
+    parts
+}
+
+/// Placeholders recognized by `--template`. Anything else in braces is kept
+/// verbatim.
+const TEMPLATE_SEGMENTS: &[&str] = &[
+    "icon",
+    "change_id",
+    "bookmarks",
+    "divergence",
+    "flags",
+    "empty",
+    "description",
+];
+
+fn render_template(template: &str, segments: &[(&'static str, String)]) -> String {
+    let mut out = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after.find('}') {
+            Some(end) if TEMPLATE_SEGMENTS.contains(&&after[..end]) => {
+                let name = &after[..end];
+                if let Some((_, value)) = segments.iter().find(|(n, _)| *n == name) {
+                    out.push_str(value);
+                }
+                rest = &after[end + 1..];
+            }
+            _ => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+
+    out
+}
+
+/// Renders segments as a JSON object keyed by segment name. Every known
+/// segment is present; absent ones are `null`.
+fn render_json(segments: &[(&'static str, String)]) -> String {
+    let object: serde_json::Map<String, serde_json::Value> = TEMPLATE_SEGMENTS
+        .iter()
+        .map(|name| {
+            let value = segments
+                .iter()
+                .find(|(n, _)| n == name)
+                .map_or(serde_json::Value::Null, |(_, v)| {
+                    serde_json::Value::String(v.clone())
+                });
+            (name.to_string(), value)
+        })
+        .collect();
+    serde_json::Value::Object(object).to_string()
+}
+
+const POWERLINE_ARROW: &str = "\u{e0b0}";
+const POWERLINE_THIN_ARROW: &str = "\u{e0b1}";
+
+/// The segment's color reduced to something usable as a background: text
+/// modifiers are dropped, `bright_` is kept.
+fn powerline_color(color: &str) -> String {
+    let mut parts: Vec<&str> = color.split('_').collect();
+    let base = parts.pop().unwrap_or(color);
+    if parts.contains(&"bright") {
+        format!("bright_{base}")
+    } else {
+        base.to_string()
+    }
+}
+
+fn powerline_background(name: &str, options: &FormatOptions) -> String {
+    let color = match name {
+        "icon" => options.icon_bg.as_deref().unwrap_or(&options.icon_color),
+        "change_id" => &options.change_id_color,
+        "bookmarks" => &options.bookmark_color,
+        "divergence" => &options.divergence_color,
+        _ => options
+            .status_bg
+            .as_deref()
+            .unwrap_or(&options.status_color),
+    };
+    powerline_color(color)
+}
+
+/// Whether `format_prompt` renders powerline segments: `--template`,
+/// `--json` and `--no-color` all turn it off.
+fn renders_powerline(options: &FormatOptions) -> bool {
+    options.powerline && !options.no_color && options.template.is_none() && !options.json
+}
+
+/// Renders uncolored segments as black text on their segment color, joined
+/// by arrow glyphs whose foreground is the previous segment's background.
+/// Adjacent segments sharing a background get a thin arrow instead.
+fn render_powerline(segments: Vec<(&'static str, String)>, options: &FormatOptions) -> String {
+    let text_color = color_to_ansi("black");
+    let mut out = String::new();
+    let mut previous: Option<String> = None;
+    for (name, text) in segments {
+        let bg = powerline_background(name, options);
+        let bg_ansi = bg_color_to_ansi(&bg);
+        match &previous {
+            Some(prev) if *prev == bg => {
+                out.push_str(&format!("{bg_ansi}{text_color}{POWERLINE_THIN_ARROW}"));
+            }
+            Some(prev) => {
+                out.push_str(&format!(
+                    "{}{bg_ansi}{POWERLINE_ARROW}",
+                    color_to_ansi(prev)
+                ));
+            }
+            None => {}
+        }
+        out.push_str(&format!("{bg_ansi}{text_color} {text} "));
+        previous = Some(bg);
+    }
+    if let Some(prev) = previous {
+        out.push_str(&format!(
+            "{ANSI_RESET}{}{POWERLINE_ARROW}{ANSI_RESET}",
+            color_to_ansi(&prev)
+        ));
+    }
+    out
+}
+
+fn format_prompt(status: &jj::JjStatus, options: &FormatOptions) -> String {
+    let mut segments = prompt_segments(status, options);
+    if options.reverse {
+        segments.reverse();
+    }
+
+    if options.json {
+        return render_json(&segments);
     }
 
-    parts.join(" ")
+    let render_line = |line: Vec<(&'static str, String)>| {
+        if renders_powerline(options) {
+            render_powerline(line, options)
+        } else {
+            line.into_iter()
+                .map(|(_, value)| value)
+                .collect::<Vec<_>>()
+                .join(&options.separator)
+        }
+    };
+    let rendered = match &options.template {
+        Some(template) => render_template(template, &segments),
+        None if options.multiline => {
+            let (second, first): (Vec<_>, Vec<_>) = segments
+                .into_iter()
+                .partition(|(name, _)| matches!(*name, "bookmarks" | "flags"));
+            if second.is_empty() {
+                render_line(first)
+            } else {
+                format!("{}\n{}", render_line(first), render_line(second))
+            }
+        }
+        None => render_line(segments),
+    };
+    format!("{}{rendered}{}", options.prefix, options.suffix)
 }
 
 impl SimplePluginCommand for JjPromptFormatCommand {
@@ -274,25 +1451,61 @@ impl SimplePluginCommand for JjPromptFormatCommand {
     }
 
     fn signature(&self) -> Signature {
-        Signature::build("jj-prompt format")
+        collect_flags(Signature::build("jj-prompt format"))
             .optional(
                 "path",
                 SyntaxShape::Filepath,
                 "Path to check (defaults to PWD)",
             )
             .named("icon", SyntaxShape::String, "Icon symbol", None)
+            .named(
+                "icon-conflict",
+                SyntaxShape::String,
+                "Icon when the commit has conflicts",
+                None,
+            )
+            .named(
+                "icon-merge",
+                SyntaxShape::String,
+                "Icon when the commit is a merge",
+                None,
+            )
+            .named(
+                "icon-empty",
+                SyntaxShape::String,
+                "Icon when the commit is empty",
+                None,
+            )
             .named(
                 "icon-color",
                 SyntaxShape::String,
                 "Icon color (default: blue)",
                 None,
             )
+            .named(
+                "icon-bg",
+                SyntaxShape::String,
+                "Icon background color (default: none)",
+                None,
+            )
             .named(
                 "change-id-color",
                 SyntaxShape::String,
                 "Change ID prefix color (default: bold_magenta)",
                 None,
             )
+            .named(
+                "stale-color",
+                SyntaxShape::String,
+                "Change ID prefix color once --stale-after-secs is exceeded (default: red)",
+                None,
+            )
+            .named(
+                "stale-after-secs",
+                SyntaxShape::Int,
+                "Seconds since authored_at before the change ID uses --stale-color (default: disabled)",
+                None,
+            )
             .named(
                 "change-id-rest-color",
                 SyntaxShape::String,
@@ -305,17 +1518,114 @@ impl SimplePluginCommand for JjPromptFormatCommand {
                 "Bookmark color (default: magenta)",
                 None,
             )
+            .named(
+                "divergence-color",
+                SyntaxShape::String,
+                "Ahead/behind color (default: cyan)",
+                None,
+            )
             .named(
                 "status-color",
                 SyntaxShape::String,
                 "Empty/description color (default: green)",
                 None,
             )
+            .named(
+                "status-bg",
+                SyntaxShape::String,
+                "Empty/description background color (default: none)",
+                None,
+            )
             .named("conflict", SyntaxShape::String, "Conflict symbol", None)
             .named("divergent", SyntaxShape::String, "Divergent symbol", None)
             .named("hidden", SyntaxShape::String, "Hidden symbol", None)
             .named("immutable", SyntaxShape::String, "Immutable symbol", None)
-            .named("change-id-len", SyntaxShape::Int, "Change ID length", None)
+            .named(
+                "conflict-color",
+                SyntaxShape::String,
+                "Conflict symbol color (default: uncolored)",
+                None,
+            )
+            .named(
+                "divergent-color",
+                SyntaxShape::String,
+                "Divergent symbol color (default: uncolored)",
+                None,
+            )
+            .named(
+                "hidden-color",
+                SyntaxShape::String,
+                "Hidden symbol color (default: uncolored)",
+                None,
+            )
+            .named(
+                "immutable-color",
+                SyntaxShape::String,
+                "Immutable symbol color (default: uncolored)",
+                None,
+            )
+            .named("merge-symbol", SyntaxShape::String, "Merge commit symbol", None)
+            .named("stale", SyntaxShape::String, "Stale working copy symbol", None)
+            .named(
+                "git-head-symbol",
+                SyntaxShape::String,
+                "Symbol when git HEAD differs from the working-copy parent",
+                None,
+            )
+            .named(
+                "signed-symbol",
+                SyntaxShape::String,
+                "Symbol when the commit has a good signature",
+                None,
+            )
+            .named(
+                "dirty-symbol",
+                SyntaxShape::String,
+                "Symbol when the working copy has file changes vs its parent",
+                None,
+            )
+            .named(
+                "push-symbol",
+                SyntaxShape::String,
+                "Symbol when the closest bookmark is ahead of its remote",
+                None,
+            )
+            .named(
+                "ahead-symbol",
+                SyntaxShape::String,
+                "Symbol before the ahead-of-trunk count",
+                None,
+            )
+            .named(
+                "behind-symbol",
+                SyntaxShape::String,
+                "Symbol before the behind-trunk count",
+                None,
+            )
+            .named(
+                "synced-symbol",
+                SyntaxShape::String,
+                "Appended to bookmarks matching their remote (default: none)",
+                None,
+            )
+            .named(
+                "unsynced-symbol",
+                SyntaxShape::String,
+                "Appended to bookmarks that differ from their remote (default: *)",
+                None,
+            )
+            .named(
+                "descendant-symbol",
+                SyntaxShape::String,
+                "Symbol before the descendant count",
+                None,
+            )
+            .switch(
+                "show-descendants",
+                "Append the working copy's descendant count to the ahead/behind segment",
+                None,
+            )
+            .named("change-id-len", SyntaxShape::Int, "Change ID length", None)
             .named(
                 "empty-text",
                 SyntaxShape::String,
@@ -328,14 +1638,815 @@ impl SimplePluginCommand for JjPromptFormatCommand {
                 "Text when no description",
                 None,
             )
+            .named(
+                "root-text",
+                SyntaxShape::String,
+                "Text in place of the description when on the root commit",
+                None,
+            )
+            .named(
+                "degraded-text",
+                SyntaxShape::String,
+                "Text shown when the working-copy commit can't be read",
+                None,
+            )
             .named(
                 "desc-len",
                 SyntaxShape::Int,
                 "Max description length before truncation",
                 None,
             )
+            .named(
+                "ellipsis",
+                SyntaxShape::String,
+                "Suffix for truncated descriptions (default: …)",
+                None,
+            )
+            .named(
+                "template",
+                SyntaxShape::String,
+                "Layout with {icon} {change_id} {bookmarks} {divergence} {flags} {empty} {description} placeholders",
+                None,
+            )
+            .switch(
+                "truncate-words",
+                "Truncate descriptions at a word boundary",
+                None,
+            )
+            .switch(
+                "full-description",
+                "Show every description line joined by spaces, not just the first",
+                None,
+            )
+            .switch("no-color", "Emit plain text without ANSI colors", None)
+            .switch("no-icon", "Omit the icon segment", None)
+            .switch("no-bookmarks", "Omit the bookmarks segment", None)
+            .switch("no-flags", "Omit the status flags segment", None)
+            .switch("no-description", "Omit the description segment", None)
+            .switch(
+                "reverse",
+                "Join segments in reverse order, icon last (for right prompts)",
+                None,
+            )
+            .switch(
+                "multiline",
+                "Put bookmarks and flags on a second line",
+                None,
+            )
+            .switch(
+                "powerline",
+                "Render segments on colored backgrounds joined by powerline arrows",
+                None,
+            )
+            .named(
+                "separator",
+                SyntaxShape::String,
+                "Text between segments (default: a single space)",
+                None,
+            )
+            .named(
+                "prefix",
+                SyntaxShape::String,
+                "Text prepended to the rendered prompt (e.g. a newline)",
+                None,
+            )
+            .named(
+                "suffix",
+                SyntaxShape::String,
+                "Text appended to the rendered prompt",
+                None,
+            )
+            .switch(
+                "json",
+                "Emit segments as a JSON object instead of a joined string",
+                None,
+            )
+            .named(
+                "max-bookmarks",
+                SyntaxShape::Int,
+                "Max bookmarks to show before a +N overflow (default: all)",
+                None,
+            )
+            .switch(
+                "show-bookmark-distance",
+                "Append +N to bookmarks that are N commits back",
+                None,
+            )
+            .named(
+                "theme",
+                SyntaxShape::String,
+                "Color preset: default, gruvbox, nord, or mono",
+                None,
+            )
+            .switch(
+                "nerd-fonts",
+                "Use Nerd Font glyphs instead of emoji for the default flag symbols",
+                None,
+            )
+            .switch(
+                "link-root",
+                "Make the icon an OSC 8 hyperlink to the repo root",
+                None,
+            )
+            .input_output_types(vec![(Type::Nothing, Type::Any)])
+            .category(Category::Custom("prompt".into()))
+    }
+
+    fn run(
+        &self,
+        plugin: &JjPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let span = call.head;
+        if prompt_disabled(engine)? {
+            return Ok(Value::nothing(span));
+        }
+        let path_str = resolve_path(engine, call)?;
+        let path = Path::new(&path_str);
+
+        let collect_options = parse_collect_options(engine, call)?;
+        let timeout = parse_timeout(call)?;
+        let result = plugin.status_cache.collect_within(
+            path,
+            &collect_options,
+            &plugin.settings_cache,
+            &plugin.prefix_cache,
+            timeout,
+        );
+        let Some(status) = prompt_status(result, call.has_flag("strict")?)? else {
+            return Ok(Value::nothing(span));
+        };
+
+        let config = load_format_config(engine, &plugin.format_config_cache)?;
+
+        let options = format_options(call, config, status.short_id_len)?;
+
+        Ok(Value::string(format_prompt(&status, &options), span))
+    }
+}
+
+struct JjLogCommand;
+
+impl SimplePluginCommand for JjLogCommand {
+    type Plugin = JjPlugin;
+
+    fn name(&self) -> &str {
+        "jj-log"
+    }
+
+    fn description(&self) -> &str {
+        "List recent ancestors of the JJ working-copy commit"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("jj-log")
+            .optional(
+                "path",
+                SyntaxShape::Filepath,
+                "Path to check (defaults to PWD)",
+            )
+            .named(
+                "limit",
+                SyntaxShape::Int,
+                "Maximum number of commits (default: 10)",
+                None,
+            )
+            .switch(
+                "count",
+                "Return only the number of commits instead of the list",
+                None,
+            )
+            .input_output_types(vec![(Type::Nothing, Type::Any)])
+            .category(Category::Custom("jj".into()))
+    }
+
+    fn run(
+        &self,
+        plugin: &JjPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let span = call.head;
+        let path_str = resolve_path(engine, call)?;
+        let path = Path::new(&path_str);
+        let limit =
+            parse_non_negative_usize("limit", call.get_flag::<i64>("limit")?.unwrap_or(10))?;
+
+        if call.has_flag("count")? {
+            return match jj::log_count(path, limit, &plugin.settings_cache) {
+                Ok(count) => Ok(Value::int(count as i64, span)),
+                Err(Error::NotARepository) => Ok(Value::nothing(span)),
+                Err(e) => Err(LabeledError::new(e.to_string())),
+            };
+        }
+
+        let entries = match jj::log(path, limit, &plugin.settings_cache) {
+            Ok(entries) => entries,
+            Err(Error::NotARepository) => return Ok(Value::nothing(span)),
+            Err(e) => return Err(LabeledError::new(e.to_string())),
+        };
+
+        let rows: Vec<Value> = entries
+            .iter()
+            .map(|entry| {
+                Value::record(
+                    record! {
+                        "change_id" => Value::string(&entry.change_id, span),
+                        "commit_id" => Value::string(&entry.commit_id, span),
+                        "description" => Value::string(&entry.description, span),
+                        "author_email" => Value::string(&entry.author_email, span),
+                        "authored_at" => Value::date(entry.authored_at, span),
+                    },
+                    span,
+                )
+            })
+            .collect();
+
+        Ok(Value::list(rows, span))
+    }
+}
+
+struct JjBookmarksCommand;
+
+impl SimplePluginCommand for JjBookmarksCommand {
+    type Plugin = JjPlugin;
+
+    fn name(&self) -> &str {
+        "jj-bookmarks"
+    }
+
+    fn description(&self) -> &str {
+        "List all local and remote JJ bookmarks"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("jj-bookmarks")
+            .optional(
+                "path",
+                SyntaxShape::Filepath,
+                "Path to check (defaults to PWD)",
+            )
+            .input_output_types(vec![(Type::Nothing, Type::Any)])
+            .category(Category::Custom("jj".into()))
+    }
+
+    fn run(
+        &self,
+        plugin: &JjPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let span = call.head;
+        let path_str = resolve_path(engine, call)?;
+        let path = Path::new(&path_str);
+
+        let bookmarks = match jj::bookmarks(path, &plugin.settings_cache) {
+            Ok(bookmarks) => bookmarks,
+            Err(Error::NotARepository) => return Ok(Value::nothing(span)),
+            Err(e) => return Err(LabeledError::new(e.to_string())),
+        };
+
+        let rows: Vec<Value> = bookmarks
+            .iter()
+            .map(|b| {
+                Value::record(
+                    record! {
+                        "name" => Value::string(&b.name, span),
+                        "remote" => b
+                            .remote
+                            .as_ref()
+                            .map_or_else(|| Value::nothing(span), |r| Value::string(r, span)),
+                        "target_change_id" => b
+                            .target_change_id
+                            .as_ref()
+                            .map_or_else(|| Value::nothing(span), |id| Value::string(id, span)),
+                        "is_tracked" => Value::bool(b.is_tracked, span),
+                    },
+                    span,
+                )
+            })
+            .collect();
+
+        Ok(Value::list(rows, span))
+    }
+}
+
+struct JjBookmarkDistanceCommand;
+
+impl SimplePluginCommand for JjBookmarkDistanceCommand {
+    type Plugin = JjPlugin;
+
+    fn name(&self) -> &str {
+        "jj-bookmark-distance"
+    }
+
+    fn description(&self) -> &str {
+        "Number of commits between the JJ working copy and a local bookmark"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("jj-bookmark-distance")
+            .required("bookmark", SyntaxShape::String, "Local bookmark name")
+            .optional(
+                "path",
+                SyntaxShape::Filepath,
+                "Path to check (defaults to PWD)",
+            )
+            .named(
+                "max-depth",
+                SyntaxShape::Int,
+                "Ancestor depth to search for the bookmark (default: 100)",
+                None,
+            )
+            .input_output_types(vec![(Type::Nothing, Type::Any)])
+            .category(Category::Custom("jj".into()))
+    }
+
+    fn run(
+        &self,
+        plugin: &JjPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let span = call.head;
+        let bookmark: String = call.req(0)?;
+        let path_str = resolve_path_at(engine, call, 1)?;
+        let path = Path::new(&path_str);
+        let max_depth = parse_non_negative_usize(
+            "max-depth",
+            call.get_flag::<i64>("max-depth")?.unwrap_or(100),
+        )?;
+
+        match jj::bookmark_distance(path, &bookmark, max_depth, &plugin.settings_cache) {
+            Ok(Some(distance)) => Ok(Value::int(distance as i64, span)),
+            Ok(None) | Err(Error::NotARepository) => Ok(Value::nothing(span)),
+            Err(e) => Err(LabeledError::new(e.to_string())),
+        }
+    }
+}
+
+struct JjOpLogCommand;
+
+impl SimplePluginCommand for JjOpLogCommand {
+    type Plugin = JjPlugin;
+
+    fn name(&self) -> &str {
+        "jj-op-log"
+    }
+
+    fn description(&self) -> &str {
+        "List recent JJ operations"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("jj-op-log")
+            .optional(
+                "path",
+                SyntaxShape::Filepath,
+                "Path to check (defaults to PWD)",
+            )
+            .named(
+                "limit",
+                SyntaxShape::Int,
+                "Maximum number of operations (default: 20)",
+                None,
+            )
+            .input_output_types(vec![(Type::Nothing, Type::Any)])
+            .category(Category::Custom("jj".into()))
+    }
+
+    fn run(
+        &self,
+        plugin: &JjPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let span = call.head;
+        let path_str = resolve_path(engine, call)?;
+        let path = Path::new(&path_str);
+        let limit =
+            parse_non_negative_usize("limit", call.get_flag::<i64>("limit")?.unwrap_or(20))?;
+
+        let entries = match jj::op_log(path, limit, &plugin.settings_cache) {
+            Ok(entries) => entries,
+            Err(Error::NotARepository) => return Ok(Value::nothing(span)),
+            Err(e) => return Err(LabeledError::new(e.to_string())),
+        };
+
+        let rows: Vec<Value> = entries
+            .iter()
+            .map(|entry| {
+                Value::record(
+                    record! {
+                        "operation_id" => Value::string(&entry.operation_id, span),
+                        "description" => Value::string(&entry.description, span),
+                        "time_started" => Value::date(entry.time_started, span),
+                        "time_ended" => Value::date(entry.time_ended, span),
+                        "user" => Value::string(&entry.user, span),
+                    },
+                    span,
+                )
+            })
+            .collect();
+
+        Ok(Value::list(rows, span))
+    }
+}
+
+struct JjWorkspacesCommand;
+
+impl SimplePluginCommand for JjWorkspacesCommand {
+    type Plugin = JjPlugin;
+
+    fn name(&self) -> &str {
+        "jj-workspaces"
+    }
+
+    fn description(&self) -> &str {
+        "List JJ workspaces with their working-copy commit"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("jj-workspaces")
+            .optional(
+                "path",
+                SyntaxShape::Filepath,
+                "Path to check (defaults to PWD)",
+            )
+            .input_output_types(vec![(Type::Nothing, Type::Any)])
+            .category(Category::Custom("jj".into()))
+    }
+
+    fn run(
+        &self,
+        plugin: &JjPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let span = call.head;
+        let path_str = resolve_path(engine, call)?;
+        let path = Path::new(&path_str);
+
+        let entries = match jj::workspaces(path, &plugin.settings_cache) {
+            Ok(entries) => entries,
+            Err(Error::NotARepository) => return Ok(Value::nothing(span)),
+            Err(e) => return Err(LabeledError::new(e.to_string())),
+        };
+
+        let rows: Vec<Value> = entries
+            .iter()
+            .map(|entry| {
+                Value::record(
+                    record! {
+                        "name" => Value::string(&entry.name, span),
+                        "change_id" => Value::string(&entry.change_id, span),
+                        "description" => Value::string(&entry.description, span),
+                        "empty" => Value::bool(entry.empty, span),
+                    },
+                    span,
+                )
+            })
+            .collect();
+
+        Ok(Value::list(rows, span))
+    }
+}
+
+struct JjStatusCommand;
+
+impl SimplePluginCommand for JjStatusCommand {
+    type Plugin = JjPlugin;
+
+    fn name(&self) -> &str {
+        "jj-status"
+    }
+
+    fn description(&self) -> &str {
+        "List files changed in the JJ working-copy commit"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("jj-status")
+            .optional(
+                "path",
+                SyntaxShape::Filepath,
+                "Path to check (defaults to PWD)",
+            )
+            .input_output_types(vec![(Type::Nothing, Type::Any)])
+            .category(Category::Custom("jj".into()))
+    }
+
+    fn run(
+        &self,
+        plugin: &JjPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let span = call.head;
+        let path_str = resolve_path(engine, call)?;
+        let path = Path::new(&path_str);
+
+        let changes = match jj::changed_files(path, &plugin.settings_cache) {
+            Ok(changes) => changes,
+            Err(Error::NotARepository) => return Ok(Value::nothing(span)),
+            Err(e) => return Err(LabeledError::new(e.to_string())),
+        };
+
+        let rows: Vec<Value> = changes
+            .iter()
+            .map(|change| {
+                Value::record(
+                    record! {
+                        "path" => Value::string(&change.path, span),
+                        "status" => Value::string(change.status.as_str(), span),
+                    },
+                    span,
+                )
+            })
+            .collect();
+
+        Ok(Value::list(rows, span))
+    }
+}
+
+struct JjDiffCommand;
+
+impl SimplePluginCommand for JjDiffCommand {
+    type Plugin = JjPlugin;
+
+    fn name(&self) -> &str {
+        "jj-diff"
+    }
+
+    fn description(&self) -> &str {
+        "Unified diff of the JJ working-copy commit against its parent"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("jj-diff")
+            .optional(
+                "path",
+                SyntaxShape::Filepath,
+                "Path to check (defaults to PWD)",
+            )
+            .named(
+                "context",
+                SyntaxShape::Int,
+                "Unchanged lines shown around each change (default: 3)",
+                None,
+            )
+            .switch(
+                "per-file",
+                "Return one row per file instead of a single string",
+                None,
+            )
+            .input_output_types(vec![(Type::Nothing, Type::Any)])
+            .category(Category::Custom("jj".into()))
+    }
+
+    fn run(
+        &self,
+        plugin: &JjPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let span = call.head;
+        let path_str = resolve_path(engine, call)?;
+        let path = Path::new(&path_str);
+        let context =
+            parse_non_negative_usize("context", call.get_flag::<i64>("context")?.unwrap_or(3))?;
+
+        let diffs = match jj::diff(path, context, &plugin.settings_cache) {
+            Ok(diffs) => diffs,
+            Err(Error::NotARepository) => return Ok(Value::nothing(span)),
+            Err(e) => return Err(LabeledError::new(e.to_string())),
+        };
+
+        if !call.has_flag("per-file")? {
+            let text: String = diffs.iter().map(|d| d.diff.as_str()).collect();
+            return Ok(Value::string(text, span));
+        }
+
+        let rows: Vec<Value> = diffs
+            .iter()
+            .map(|d| {
+                Value::record(
+                    record! {
+                        "path" => Value::string(&d.path, span),
+                        "status" => Value::string(d.status.as_str(), span),
+                        "diff" => Value::string(&d.diff, span),
+                    },
+                    span,
+                )
+            })
+            .collect();
+
+        Ok(Value::list(rows, span))
+    }
+}
+
+struct JjDescribeCommand;
+
+impl SimplePluginCommand for JjDescribeCommand {
+    type Plugin = JjPlugin;
+
+    fn name(&self) -> &str {
+        "jj-describe"
+    }
+
+    fn description(&self) -> &str {
+        "Full description of the JJ working-copy commit"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("jj-describe")
+            .optional(
+                "path",
+                SyntaxShape::Filepath,
+                "Path to check (defaults to PWD)",
+            )
+            .input_output_types(vec![(Type::Nothing, Type::Any)])
+            .category(Category::Custom("jj".into()))
+    }
+
+    fn run(
+        &self,
+        plugin: &JjPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let span = call.head;
+        let path_str = resolve_path(engine, call)?;
+        let path = Path::new(&path_str);
+
+        match jj::description(path, &plugin.settings_cache) {
+            Ok(description) => Ok(Value::string(description, span)),
+            Err(Error::NotARepository) => Ok(Value::nothing(span)),
+            Err(e) => Err(LabeledError::new(e.to_string())),
+        }
+    }
+}
+
+struct JjResolveCommand;
+
+impl SimplePluginCommand for JjResolveCommand {
+    type Plugin = JjPlugin;
+
+    fn name(&self) -> &str {
+        "jj-resolve"
+    }
+
+    fn description(&self) -> &str {
+        "Resolve a JJ revset to the change IDs it names"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("jj-resolve")
+            .required(
+                "revset",
+                SyntaxShape::String,
+                "Revset to resolve (e.g. trunk() or @-)",
+            )
+            .optional(
+                "path",
+                SyntaxShape::Filepath,
+                "Path to check (defaults to PWD)",
+            )
+            .named(
+                "limit",
+                SyntaxShape::Int,
+                "Maximum number of change IDs (default: 100)",
+                None,
+            )
+            .input_output_types(vec![(Type::Nothing, Type::Any)])
+            .category(Category::Custom("jj".into()))
+    }
+
+    fn run(
+        &self,
+        plugin: &JjPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let span = call.head;
+        let revset: String = call.req(0)?;
+        let path_str = resolve_path_at(engine, call, 1)?;
+        let path = Path::new(&path_str);
+        let limit =
+            parse_non_negative_usize("limit", call.get_flag::<i64>("limit")?.unwrap_or(100))?;
+
+        let change_ids = match jj::resolve(path, &revset, limit, &plugin.settings_cache) {
+            Ok(change_ids) => change_ids,
+            Err(Error::NotARepository) => return Ok(Value::nothing(span)),
+            Err(e) => return Err(LabeledError::new(e.to_string())),
+        };
+
+        let ids: Vec<Value> = change_ids
+            .iter()
+            .map(|id| Value::string(id, span))
+            .collect();
+        Ok(Value::list(ids, span))
+    }
+}
+
+struct JjConflictsCommand;
+
+impl SimplePluginCommand for JjConflictsCommand {
+    type Plugin = JjPlugin;
+
+    fn name(&self) -> &str {
+        "jj-conflicts"
+    }
+
+    fn description(&self) -> &str {
+        "List conflicted files in the JJ working-copy commit"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("jj-conflicts")
+            .optional(
+                "path",
+                SyntaxShape::Filepath,
+                "Path to check (defaults to PWD)",
+            )
+            .named(
+                "revision",
+                SyntaxShape::String,
+                "Revset naming a single commit to check instead of the working copy",
+                None,
+            )
+            .input_output_types(vec![(Type::Nothing, Type::Any)])
+            .category(Category::Custom("jj".into()))
+    }
+
+    fn run(
+        &self,
+        plugin: &JjPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let span = call.head;
+        let path_str = resolve_path(engine, call)?;
+        let path = Path::new(&path_str);
+        let revision = call.get_flag::<String>("revision")?;
+
+        let conflicts = match jj::conflicts(path, revision.as_deref(), &plugin.settings_cache) {
+            Ok(conflicts) => conflicts,
+            Err(Error::NotARepository) => return Ok(Value::nothing(span)),
+            Err(e) => return Err(LabeledError::new(e.to_string())),
+        };
+
+        let rows: Vec<Value> = conflicts
+            .iter()
+            .map(|c| {
+                Value::record(
+                    record! {
+                        "path" => Value::string(&c.path, span),
+                        "num_sides" => Value::int(c.num_sides as i64, span),
+                    },
+                    span,
+                )
+            })
+            .collect();
+
+        Ok(Value::list(rows, span))
+    }
+}
+
+struct JjRootCommand;
+
+impl SimplePluginCommand for JjRootCommand {
+    type Plugin = JjPlugin;
+
+    fn name(&self) -> &str {
+        "jj-root"
+    }
+
+    fn description(&self) -> &str {
+        "Return the root of the JJ workspace containing a path"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("jj-root")
+            .optional(
+                "path",
+                SyntaxShape::Filepath,
+                "Path to check (defaults to PWD)",
+            )
             .input_output_types(vec![(Type::Nothing, Type::Any)])
-            .category(Category::Custom("prompt".into()))
+            .category(Category::Custom("jj".into()))
     }
 
     fn run(
@@ -349,66 +2460,67 @@ impl SimplePluginCommand for JjPromptFormatCommand {
         let path_str = resolve_path(engine, call)?;
         let path = Path::new(&path_str);
 
-        let status = match jj::collect(path) {
-            Ok(Some(s)) => s,
-            Ok(None) | Err(_) => return Ok(Value::nothing(span)),
-        };
+        match jj::root(path) {
+            Ok(root) => Ok(Value::string(root.to_string_lossy(), span)),
+            Err(Error::NotARepository) => Ok(Value::nothing(span)),
+            Err(e) => Err(LabeledError::new(e.to_string())),
+        }
+    }
+}
 
-        let options = FormatOptions {
-            icon: call
-                .get_flag::<String>("icon")?
-                .unwrap_or_else(|| "󱗆".to_string()),
-            icon_color: call
-                .get_flag::<String>("icon-color")?
-                .unwrap_or_else(|| "blue".to_string()),
-            change_id_color: call
-                .get_flag::<String>("change-id-color")?
-                .unwrap_or_else(|| "bold_magenta".to_string()),
-            change_id_rest_color: call
-                .get_flag::<String>("change-id-rest-color")?
-                .unwrap_or_else(|| "dim_magenta".to_string()),
-            bookmark_color: call
-                .get_flag::<String>("bookmark-color")?
-                .unwrap_or_else(|| "magenta".to_string()),
-            status_color: call
-                .get_flag::<String>("status-color")?
-                .unwrap_or_else(|| "green".to_string()),
-            conflict: call
-                .get_flag::<String>("conflict")?
-                .unwrap_or_else(|| "💥".to_string()),
-            divergent: call
-                .get_flag::<String>("divergent")?
-                .unwrap_or_else(|| "🚧".to_string()),
-            hidden: call
-                .get_flag::<String>("hidden")?
-                .unwrap_or_else(|| "👻".to_string()),
-            immutable: call
-                .get_flag::<String>("immutable")?
-                .unwrap_or_else(|| "🔒".to_string()),
-            change_id_len: parse_non_negative_usize(
-                "change-id-len",
-                call.get_flag::<i64>("change-id-len")?.unwrap_or(8),
-            )?,
-            empty_text: call
-                .get_flag::<String>("empty-text")?
-                .unwrap_or_else(|| "(empty)".to_string()),
-            no_desc_text: call
-                .get_flag::<String>("no-desc-text")?
-                .unwrap_or_else(|| "(no description set)".to_string()),
-            desc_len: parse_non_negative_usize(
-                "desc-len",
-                call.get_flag::<i64>("desc-len")?.unwrap_or(29),
-            )?,
-        };
+/// The jj-lib version `Cargo.toml` pins exactly; keep the two in step.
+const JJ_LIB_VERSION: &str = "0.41.0";
 
-        Ok(Value::string(format_prompt(&status, &options), span))
+struct JjVersionCommand;
+
+impl SimplePluginCommand for JjVersionCommand {
+    type Plugin = JjPlugin;
+
+    fn name(&self) -> &str {
+        "jj-version"
+    }
+
+    fn description(&self) -> &str {
+        "Return the plugin version and the jj library version it embeds"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build("jj-version")
+            .input_output_types(vec![(Type::Nothing, Type::Any)])
+            .category(Category::Custom("jj".into()))
+    }
+
+    fn run(
+        &self,
+        _plugin: &JjPlugin,
+        _engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let span = call.head;
+        Ok(Value::record(
+            record! {
+                "plugin_version" => Value::string(env!("CARGO_PKG_VERSION"), span),
+                "jj_lib_version" => Value::string(JJ_LIB_VERSION, span),
+            },
+            span,
+        ))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{FormatOptions, color_to_ansi, format_prompt, parse_non_negative_usize};
-    use crate::jj::{Bookmark, JjStatus};
+    use super::{
+        EMOJI_SYMBOLS, FormatOptions, JJ_LIB_VERSION, NERD_FONT_SYMBOLS, bg_color_to_ansi,
+        color_to_ansi, find_theme, format_options, format_prompt, is_disable_value,
+        parse_non_negative_usize, powerline_color, prompt_status, split_change_id,
+    };
+    use crate::config::FormatConfig;
+    use crate::error::Error;
+    use crate::jj::{Bookmark, Diagnostics, JjStatus, SignatureStatus};
+    use chrono::DateTime;
+    use nu_plugin::EvaluatedCall;
+    use nu_protocol::{Span, Spanned, Value};
 
     fn strip_ansi(input: &str) -> String {
         let mut out = String::new();
@@ -421,6 +2533,15 @@ mod tests {
                         break;
                     }
                 }
+            } else if ch == '\u{1b}' && chars.peek() == Some(&']') {
+                // OSC sequences run until the string terminator `ESC \`.
+                chars.next();
+                while let Some(c) = chars.next() {
+                    if c == '\u{1b}' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
             } else {
                 out.push(ch);
             }
@@ -431,42 +2552,164 @@ mod tests {
     fn test_status(description: &str) -> JjStatus {
         JjStatus {
             repo_root: "/tmp/repo".to_string(),
+            path_in_repo: Some(String::new()),
+            operation_id: "0123abcd".to_string(),
+            colocated: false,
+            degraded: false,
             change_id: "abcdefgh".to_string(),
+            change_id_full: "abcdefghijklmnopqrstuvwxyzabcdef".to_string(),
             change_id_prefix_len: 4,
+            short_id_len: 8,
+            commit_id: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            commit_id_prefix_len: 4,
+            parents: vec!["zzzzzzzz".to_string()],
+            is_merge: false,
+            is_root: false,
             bookmarks: vec![Bookmark {
                 name: "main".to_string(),
                 distance: 0,
+                target: "abcdefgh".to_string(),
+                is_tracked: true,
+                has_remote: false,
+                is_synced: true,
+                needs_push: false,
             }],
             description: description.to_string(),
+            description_full: format!("{description}\n"),
+            author_name: "Test User".to_string(),
+            author_email: "test@example.com".to_string(),
+            authored_by_me: true,
+            committer_email: "test@example.com".to_string(),
+            authored_at: DateTime::UNIX_EPOCH.fixed_offset(),
+            age_seconds: 0,
+            age_human: "just now".to_string(),
             empty: true,
+            files_added: 0,
+            files_modified: 0,
+            files_deleted: 0,
+            lines_added: 0,
+            lines_removed: 0,
+            new_files: 0,
+            dirty: false,
             conflict: true,
+            conflict_count: 0,
+            conflicted_files: Vec::new(),
+            conflicts: Vec::new(),
             divergent: false,
+            divergent_count: 1,
             hidden: true,
+            hidden_reason: Some("obsoleted".to_string()),
             immutable: false,
+            is_stale: false,
+            git_head_behind: false,
+            signed: false,
+            signature_status: SignatureStatus::Unknown,
             has_remote: true,
             is_synced: true,
+            needs_push: false,
+            has_trunk: false,
+            on_trunk: false,
+            ahead: 0,
+            behind: 0,
+            descendant_count: 0,
+            diagnostics: Diagnostics::default(),
         }
     }
 
     fn test_options(desc_len: usize) -> FormatOptions {
         FormatOptions {
             icon: "*".to_string(),
+            icon_conflict: None,
+            icon_merge: None,
+            icon_empty: None,
             icon_color: "blue".to_string(),
+            icon_bg: None,
             change_id_color: "bold_magenta".to_string(),
             change_id_rest_color: "dim_magenta".to_string(),
+            stale_color: "red".to_string(),
+            stale_after_secs: None,
             bookmark_color: "magenta".to_string(),
+            divergence_color: "cyan".to_string(),
             status_color: "green".to_string(),
+            status_bg: None,
             conflict: "C".to_string(),
             divergent: "D".to_string(),
             hidden: "H".to_string(),
             immutable: "I".to_string(),
+            conflict_color: None,
+            divergent_color: None,
+            hidden_color: None,
+            immutable_color: None,
+            merge: "M".to_string(),
+            stale: "S".to_string(),
+            git_head: "G".to_string(),
+            push: "P".to_string(),
+            signed: "V".to_string(),
+            dirty: "*".to_string(),
+            ahead_symbol: "^".to_string(),
+            behind_symbol: "v".to_string(),
+            descendant_symbol: "d".to_string(),
+            synced_symbol: String::new(),
+            unsynced_symbol: "*".to_string(),
+            show_descendants: false,
             change_id_len: 8,
             empty_text: "(empty)".to_string(),
             no_desc_text: "(no description set)".to_string(),
+            root_text: "(root)".to_string(),
+            degraded_text: "(degraded)".to_string(),
             desc_len,
+            ellipsis: "…".to_string(),
+            truncate_words: false,
+            full_description: false,
+            template: None,
+            no_color: false,
+            json: false,
+            show_bookmark_distance: false,
+            max_bookmarks: None,
+            link_root: false,
+            no_icon: false,
+            no_bookmarks: false,
+            no_flags: false,
+            no_description: false,
+            reverse: false,
+            multiline: false,
+            prefix: String::new(),
+            suffix: String::new(),
+            separator: " ".to_string(),
+            powerline: false,
         }
     }
 
+    #[test]
+    fn strict_surfaces_real_failures_only() {
+        let not_repo = || Err(Error::NotARepository);
+        let failure = || Err(Error::Jj("load repo: boom".to_string()));
+
+        assert!(prompt_status(not_repo(), false).unwrap().is_none());
+        assert!(prompt_status(not_repo(), true).unwrap().is_none());
+        assert!(prompt_status(failure(), false).unwrap().is_none());
+        assert!(
+            prompt_status(Err(Error::Timeout(50)), true)
+                .unwrap()
+                .is_none()
+        );
+
+        let err = prompt_status(failure(), true).unwrap_err();
+        assert!(err.to_string().contains("boom"));
+
+        let status = prompt_status(Ok(test_status("desc")), true).unwrap();
+        assert!(status.is_some());
+    }
+
+    #[test]
+    fn disable_env_var_values() {
+        assert!(is_disable_value("1"));
+        assert!(is_disable_value("true"));
+        assert!(!is_disable_value(""));
+        assert!(!is_disable_value("0"));
+        assert!(!is_disable_value("false"));
+    }
+
     #[test]
     fn rejects_negative_lengths() {
         let err = parse_non_negative_usize("desc-len", -1).unwrap_err();
@@ -484,6 +2727,21 @@ mod tests {
         assert_eq!(color_to_ansi("bright_#112233"), "\x1b[1;38;2;17;34;51m");
     }
 
+    #[test]
+    fn indexed_color_applies_modifier() {
+        assert_eq!(color_to_ansi("color123"), "\x1b[38;5;123m");
+        assert_eq!(color_to_ansi("c7"), "\x1b[38;5;7m");
+        assert_eq!(color_to_ansi("bold_color123"), "\x1b[1;38;5;123m");
+        assert_eq!(color_to_ansi("bright_c200"), "\x1b[1;38;5;200m");
+    }
+
+    #[test]
+    fn out_of_range_indexed_color_falls_back_to_magenta() {
+        assert_eq!(color_to_ansi("color256"), "\x1b[35m");
+        assert_eq!(color_to_ansi("c"), "\x1b[35m");
+        assert_eq!(color_to_ansi("c-1"), "\x1b[35m");
+    }
+
     #[test]
     fn bright_named_color_uses_bright_ansi() {
         assert_eq!(color_to_ansi("bright_red"), "\x1b[91m");
@@ -495,17 +2753,638 @@ mod tests {
         assert_eq!(value, 0);
     }
 
+    #[test]
+    fn single_attributes_are_unchanged() {
+        assert_eq!(color_to_ansi("red"), "\x1b[31m");
+        assert_eq!(color_to_ansi("bold_red"), "\x1b[1;31m");
+        assert_eq!(color_to_ansi("dim_magenta"), "\x1b[2;35m");
+        assert_eq!(color_to_ansi("bold_#112233"), "\x1b[1;38;2;17;34;51m");
+    }
+
+    #[test]
+    fn multiple_attributes_combine() {
+        assert_eq!(color_to_ansi("bold_underline_red"), "\x1b[1;4;31m");
+        assert_eq!(color_to_ansi("italic_dim_blue"), "\x1b[3;2;34m");
+        assert_eq!(color_to_ansi("bright_bold_red"), "\x1b[1;91m");
+        assert_eq!(
+            color_to_ansi("bold_bright_#112233"),
+            "\x1b[1;38;2;17;34;51m"
+        );
+        assert_eq!(color_to_ansi("underline_bright_c42"), "\x1b[4;1;38;5;42m");
+        assert_eq!(color_to_ansi("bold_nope_red"), "\x1b[35m");
+    }
+
+    #[test]
+    fn strike_reverse_and_blink_attributes() {
+        assert_eq!(color_to_ansi("strike_red"), "\x1b[9;31m");
+        assert_eq!(color_to_ansi("reverse_green"), "\x1b[7;32m");
+        assert_eq!(color_to_ansi("blink_yellow"), "\x1b[5;33m");
+        assert_eq!(color_to_ansi("strike_#112233"), "\x1b[9;38;2;17;34;51m");
+        assert_eq!(color_to_ansi("strike_bright_red"), "\x1b[9;91m");
+        assert_eq!(
+            color_to_ansi("bright_strike_#112233"),
+            "\x1b[1;9;38;2;17;34;51m"
+        );
+    }
+
     #[test]
     fn invalid_color_falls_back_to_magenta() {
         assert_eq!(color_to_ansi("unknown"), "\x1b[35m");
         assert_eq!(color_to_ansi("bad_red"), "\x1b[35m");
     }
 
+    #[test]
+    fn bg_color_supports_named_hex_and_indexed() {
+        assert_eq!(bg_color_to_ansi("blue"), "\x1b[44m");
+        assert_eq!(bg_color_to_ansi("bright_blue"), "\x1b[104m");
+        assert_eq!(bg_color_to_ansi("#112233"), "\x1b[48;2;17;34;51m");
+        assert_eq!(bg_color_to_ansi("color236"), "\x1b[48;5;236m");
+        assert_eq!(bg_color_to_ansi("unknown"), "");
+    }
+
+    #[test]
+    fn bg_color_is_merged_into_segment() {
+        let mut options = test_options(29);
+        options.icon_bg = Some("blue".to_string());
+        let rendered = format_prompt(&test_status("desc"), &options);
+        assert!(rendered.starts_with("\x1b[34m\x1b[44m*\x1b[0m"));
+        assert_eq!(strip_ansi(&rendered), "* abcdefgh main CH (empty) desc");
+    }
+
+    #[test]
+    fn old_change_id_uses_stale_color() {
+        let mut status = test_status("desc");
+        status.age_seconds = 3600;
+        let mut options = test_options(29);
+        assert!(!format_prompt(&status, &options).contains("\x1b[31m"));
+
+        options.stale_after_secs = Some(3600);
+        assert!(!format_prompt(&status, &options).contains("\x1b[31m"));
+
+        options.stale_after_secs = Some(60);
+        let rendered = format_prompt(&status, &options);
+        assert!(rendered.contains("\x1b[31m"));
+        assert_eq!(strip_ansi(&rendered), "* abcdefgh main CH (empty) desc");
+    }
+
+    #[test]
+    fn flag_colors_apply_per_symbol() {
+        let status = test_status("desc");
+        let mut options = test_options(29);
+        let rendered = format_prompt(&status, &options);
+        assert!(rendered.contains(" CH "));
+
+        options.conflict_color = Some("red".to_string());
+        let rendered = format_prompt(&status, &options);
+        assert!(rendered.contains("\x1b[31mC\x1b[0mH"));
+        assert_eq!(strip_ansi(&rendered), "* abcdefgh main CH (empty) desc");
+
+        options.no_color = true;
+        assert!(format_prompt(&status, &options).contains(" CH "));
+    }
+
+    fn call_with_flags(flags: &[&str]) -> EvaluatedCall {
+        let mut call = EvaluatedCall::new(Span::test_data());
+        for flag in flags {
+            call.add_flag(Spanned {
+                item: *flag,
+                span: Span::test_data(),
+            });
+        }
+        call
+    }
+
+    #[test]
+    fn nerd_fonts_flag_renders_nerd_font_glyphs() {
+        let mut status = test_status("desc");
+        status.needs_push = true;
+        status.dirty = true;
+        let render = |flags: &[&str]| {
+            let options =
+                format_options(&call_with_flags(flags), FormatConfig::default(), 8).unwrap();
+            strip_ansi(&format_prompt(&status, &options))
+        };
+
+        let emoji = render(&[]);
+        assert!(emoji.contains(&format!("{}{}", EMOJI_SYMBOLS.push, EMOJI_SYMBOLS.dirty)));
+
+        let nerd = render(&["nerd-fonts"]);
+        assert!(nerd.contains(&format!(
+            "{}{}",
+            NERD_FONT_SYMBOLS.push, NERD_FONT_SYMBOLS.dirty
+        )));
+        assert!(!nerd.contains(EMOJI_SYMBOLS.push));
+        assert!(!nerd.contains(EMOJI_SYMBOLS.dirty));
+    }
+
+    #[test]
+    fn explicit_switch_overrides_config_file() {
+        let config = FormatConfig {
+            no_icon: true,
+            ..FormatConfig::default()
+        };
+        let options = format_options(&call_with_flags(&[]), config.clone(), 8).unwrap();
+        assert!(options.no_icon);
+
+        let mut call = call_with_flags(&[]);
+        call.add_named(
+            Spanned {
+                item: "no-icon",
+                span: Span::test_data(),
+            },
+            Value::test_bool(false),
+        );
+        let options = format_options(&call, config, 8).unwrap();
+        assert!(!options.no_icon);
+    }
+
+    #[test]
+    fn multiline_moves_bookmarks_and_flags_to_second_line() {
+        let mut options = test_options(29);
+        options.multiline = true;
+        let plain = strip_ansi(&format_prompt(&test_status("desc"), &options));
+        assert_eq!(plain, "* abcdefgh (empty) desc\nmain CH");
+
+        options.prefix = "\n".to_string();
+        options.suffix = " > ".to_string();
+        let plain = strip_ansi(&format_prompt(&test_status("desc"), &options));
+        assert_eq!(plain, "\n* abcdefgh (empty) desc\nmain CH > ");
+    }
+
+    #[test]
+    fn degraded_status_renders_warning_only() {
+        let mut status = test_status("desc");
+        status.degraded = true;
+        let mut options = test_options(29);
+        let plain = strip_ansi(&format_prompt(&status, &options));
+        assert_eq!(plain, "* (degraded)");
+
+        options.no_description = true;
+        let plain = strip_ansi(&format_prompt(&status, &options));
+        assert_eq!(plain, "*");
+    }
+
+    #[test]
+    fn powerline_uses_segment_colors_as_backgrounds() {
+        let mut options = test_options(29);
+        options.powerline = true;
+        let rendered = format_prompt(&test_status("desc"), &options);
+        assert!(rendered.starts_with("\x1b[44m\x1b[30m * "));
+        // blue icon -> magenta change id
+        assert!(rendered.contains("\x1b[34m\x1b[45m\u{e0b0}"));
+        assert_eq!(
+            strip_ansi(&rendered),
+            " * \u{e0b0} abcdefgh \u{e0b1} main \u{e0b0} CH \u{e0b1} (empty) \u{e0b1} desc \u{e0b0}"
+        );
+
+        options.no_color = true;
+        let plain = format_prompt(&test_status("desc"), &options);
+        assert_eq!(plain, "* abcdefgh main CH (empty) desc");
+    }
+
+    #[test]
+    fn powerline_renders_each_multiline_line() {
+        let mut options = test_options(29);
+        options.powerline = true;
+        options.multiline = true;
+        let plain = strip_ansi(&format_prompt(&test_status("desc"), &options));
+        assert_eq!(
+            plain,
+            " * \u{e0b0} abcdefgh \u{e0b0} (empty) \u{e0b1} desc \u{e0b0}\n main \u{e0b0} CH \u{e0b0}"
+        );
+    }
+
+    #[test]
+    fn powerline_rejects_separator() {
+        let mut call = call_with_flags(&["powerline"]);
+        call.add_named(
+            Spanned {
+                item: "separator",
+                span: Span::test_data(),
+            },
+            Value::test_string(" | "),
+        );
+        assert!(format_options(&call, FormatConfig::default(), 8).is_err());
+        assert!(
+            format_options(&call_with_flags(&["powerline"]), FormatConfig::default(), 8).is_ok()
+        );
+    }
+
+    #[test]
+    fn powerline_color_drops_text_modifiers() {
+        assert_eq!(powerline_color("bold_magenta"), "magenta");
+        assert_eq!(powerline_color("bold_bright_red"), "bright_red");
+        assert_eq!(powerline_color("dim_#d3869b"), "#d3869b");
+        assert_eq!(powerline_color("color123"), "color123");
+    }
+
+    #[test]
+    fn state_icons_follow_precedence() {
+        let mut status = test_status("desc");
+        let mut options = test_options(29);
+        options.icon_empty = Some("E".to_string());
+        options.icon_merge = Some("M".to_string());
+        let plain = strip_ansi(&format_prompt(&status, &options));
+        assert!(plain.starts_with("E "));
+
+        status.is_merge = true;
+        let plain = strip_ansi(&format_prompt(&status, &options));
+        assert!(plain.starts_with("M "));
+
+        // conflict wins, but only once its icon is set
+        status.conflict = true;
+        let plain = strip_ansi(&format_prompt(&status, &options));
+        assert!(plain.starts_with("M "));
+        options.icon_conflict = Some("X".to_string());
+        let plain = strip_ansi(&format_prompt(&status, &options));
+        assert!(plain.starts_with("X "));
+    }
+
+    #[test]
+    fn bookmark_sync_symbols_need_a_remote() {
+        let mut status = test_status("desc");
+        let mut options = test_options(29);
+        options.synced_symbol = "=".to_string();
+        let plain = strip_ansi(&format_prompt(&status, &options));
+        assert_eq!(plain, "* abcdefgh main CH (empty) desc");
+
+        status.bookmarks[0].has_remote = true;
+        let plain = strip_ansi(&format_prompt(&status, &options));
+        assert_eq!(plain, "* abcdefgh main= CH (empty) desc");
+
+        status.bookmarks[0].is_synced = false;
+        status.bookmarks[0].distance = 2;
+        options.show_bookmark_distance = true;
+        let plain = strip_ansi(&format_prompt(&status, &options));
+        assert_eq!(plain, "* abcdefgh main+2* CH (empty) desc");
+    }
+
+    #[test]
+    fn short_change_id_does_not_panic() {
+        let mut status = test_status("desc");
+        status.change_id_full = "zz".to_string();
+        status.change_id_prefix_len = 4;
+        let plain = strip_ansi(&format_prompt(&status, &test_options(29)));
+        assert_eq!(plain, "* zz main CH (empty) desc");
+
+        status.change_id_full = String::new();
+        let plain = strip_ansi(&format_prompt(&status, &test_options(29)));
+        assert_eq!(plain, "* main CH (empty) desc");
+    }
+
+    #[test]
+    fn prefix_longer_than_change_id_len_does_not_panic() {
+        assert_eq!(split_change_id("abcdefgh", 2, 4), ("ab", ""));
+        assert_eq!(split_change_id("abcdefgh", 4, 2), ("ab", "cd"));
+        assert_eq!(split_change_id("ab", 8, 4), ("ab", ""));
+        assert_eq!(split_change_id("", 8, 4), ("", ""));
+
+        let mut status = test_status("desc");
+        status.change_id_prefix_len = 6;
+        let mut options = test_options(29);
+        options.change_id_len = 3;
+        let plain = strip_ansi(&format_prompt(&status, &options));
+        assert_eq!(plain, "* abc main CH (empty) desc");
+    }
+
+    #[test]
+    fn change_id_is_cut_on_char_boundaries() {
+        let mut status = test_status("desc");
+        status.change_id_full = "ééééééééé".to_string();
+        status.change_id_prefix_len = 3;
+        let rendered = format_prompt(&status, &test_options(29));
+        assert_eq!(strip_ansi(&rendered), "* éééééééé main CH (empty) desc");
+        assert!(rendered.contains("ééé\x1b[0m"));
+    }
+
+    #[test]
+    fn full_description_joins_lines() {
+        let mut status = test_status("subject");
+        status.description_full = "subject\n\nbody line one\nbody line two\n".to_string();
+        let mut options = test_options(29);
+        options.full_description = true;
+        let plain = strip_ansi(&format_prompt(&status, &options));
+        assert_eq!(
+            plain,
+            "* abcdefgh main CH (empty) subject body line one body li…"
+        );
+
+        options.full_description = false;
+        let plain = strip_ansi(&format_prompt(&status, &options));
+        assert_eq!(plain, "* abcdefgh main CH (empty) subject");
+    }
+
     #[test]
     fn format_output_order_is_stable() {
         let rendered = format_prompt(&test_status("desc"), &test_options(29));
         let plain = strip_ansi(&rendered);
         assert_eq!(plain, "* abcdefgh main CH (empty) desc");
+
+        let mut options = test_options(29);
+        options.separator = " | ".to_string();
+        let plain = strip_ansi(&format_prompt(&test_status("desc"), &options));
+        assert_eq!(plain, "* | abcdefgh | main | CH | (empty) | desc");
+    }
+
+    #[test]
+    fn disabled_segments_are_omitted_without_extra_spaces() {
+        let options = FormatOptions {
+            no_icon: true,
+            no_flags: true,
+            ..test_options(29)
+        };
+        let plain = strip_ansi(&format_prompt(&test_status("desc"), &options));
+        assert_eq!(plain, "abcdefgh main (empty) desc");
+
+        let options = FormatOptions {
+            no_bookmarks: true,
+            no_description: true,
+            ..test_options(29)
+        };
+        let plain = strip_ansi(&format_prompt(&test_status("desc"), &options));
+        assert_eq!(plain, "* abcdefgh CH (empty)");
+    }
+
+    #[test]
+    fn reverse_flips_segment_order() {
+        let options = FormatOptions {
+            reverse: true,
+            ..test_options(29)
+        };
+        let plain = strip_ansi(&format_prompt(&test_status("desc"), &options));
+        assert_eq!(plain, "desc (empty) CH main abcdefgh *");
+    }
+
+    #[test]
+    fn change_id_len_can_exceed_short_id() {
+        let options = FormatOptions {
+            change_id_len: 12,
+            ..test_options(29)
+        };
+        let plain = strip_ansi(&format_prompt(&test_status("desc"), &options));
+        assert_eq!(plain, "* abcdefghijkl main CH (empty) desc");
+    }
+
+    #[test]
+    fn template_controls_segment_order() {
+        let mut options = test_options(29);
+        options.template = Some("[{description}] {change_id}{flags} on {bookmarks}".to_string());
+        let plain = strip_ansi(&format_prompt(&test_status("desc"), &options));
+        assert_eq!(plain, "[desc] abcdefghCH on main");
+    }
+
+    #[test]
+    fn template_keeps_unknown_placeholders_literal() {
+        let mut options = test_options(29);
+        options.template = Some("{icon} {nope} {change_id".to_string());
+        let plain = strip_ansi(&format_prompt(&test_status("desc"), &options));
+        assert_eq!(plain, "* {nope} {change_id");
+    }
+
+    #[test]
+    fn template_renders_absent_segments_as_empty() {
+        let mut status = test_status("desc");
+        status.bookmarks.clear();
+        let mut options = test_options(29);
+        options.template = Some("{change_id}<{bookmarks}>".to_string());
+        let plain = strip_ansi(&format_prompt(&status, &options));
+        assert_eq!(plain, "abcdefgh<>");
+    }
+
+    #[test]
+    fn no_color_matches_stripped_output() {
+        let status = test_status("desc");
+        let mut options = test_options(29);
+        options.icon_bg = Some("blue".to_string());
+        let colored = format_prompt(&status, &options);
+
+        options.no_color = true;
+        let plain = format_prompt(&status, &options);
+        assert!(!plain.contains('\u{1b}'));
+        assert_eq!(plain, strip_ansi(&colored));
+    }
+
+    #[test]
+    fn bookmark_distance_is_shown_when_requested() {
+        let mut status = test_status("desc");
+        status.bookmarks.push(Bookmark {
+            name: "feature".to_string(),
+            distance: 3,
+            target: "kkkkkkkk".to_string(),
+            is_tracked: false,
+            has_remote: false,
+            is_synced: true,
+            needs_push: false,
+        });
+        let mut options = test_options(29);
+
+        let plain = strip_ansi(&format_prompt(&status, &options));
+        assert_eq!(plain, "* abcdefgh main feature CH (empty) desc");
+
+        options.show_bookmark_distance = true;
+        let plain = strip_ansi(&format_prompt(&status, &options));
+        assert_eq!(plain, "* abcdefgh main feature+3 CH (empty) desc");
+    }
+
+    #[test]
+    fn link_root_wraps_icon_in_hyperlink() {
+        let mut status = test_status("desc");
+        status.repo_root = "/tmp/my repo".to_string();
+        let options = FormatOptions {
+            link_root: true,
+            no_color: true,
+            ..test_options(29)
+        };
+
+        let out = format_prompt(&status, &options);
+        assert!(out.starts_with("\x1b]8;;file:///tmp/my%20repo\x1b\\*\x1b]8;;\x1b\\ "));
+        assert_eq!(strip_ansi(&out), "* abcdefgh main CH (empty) desc");
+    }
+
+    #[test]
+    fn themes_resolve_by_name() {
+        assert_eq!(find_theme("default").unwrap().icon, "blue");
+        assert_eq!(find_theme("nord").unwrap().status, "#a3be8c");
+        for name in ["gruvbox", "mono"] {
+            let theme = find_theme(name).unwrap();
+            assert_ne!(color_to_ansi(theme.change_id), "\x1b[35m");
+        }
+
+        let err = find_theme("solarized").unwrap_err();
+        assert!(err.to_string().contains("gruvbox"));
+    }
+
+    #[test]
+    fn divergence_segment_follows_bookmarks() {
+        let mut status = test_status("desc");
+        status.ahead = 2;
+        status.behind = 1;
+        let plain = strip_ansi(&format_prompt(&status, &test_options(29)));
+        assert_eq!(plain, "* abcdefgh main ^2v1 CH (empty) desc");
+
+        status.behind = 0;
+        let plain = strip_ansi(&format_prompt(&status, &test_options(29)));
+        assert_eq!(plain, "* abcdefgh main ^2 CH (empty) desc");
+    }
+
+    #[test]
+    fn descendants_are_shown_only_when_requested() {
+        let mut status = test_status("desc");
+        status.ahead = 1;
+        status.descendant_count = 3;
+        let mut options = test_options(29);
+
+        let plain = strip_ansi(&format_prompt(&status, &options));
+        assert_eq!(plain, "* abcdefgh main ^1 CH (empty) desc");
+
+        options.show_descendants = true;
+        let plain = strip_ansi(&format_prompt(&status, &options));
+        assert_eq!(plain, "* abcdefgh main ^1d3 CH (empty) desc");
+    }
+
+    #[test]
+    fn merge_symbol_appends_to_flags() {
+        let mut status = test_status("desc");
+        status.is_merge = true;
+        let plain = strip_ansi(&format_prompt(&status, &test_options(29)));
+        assert_eq!(plain, "* abcdefgh main CHM (empty) desc");
+    }
+
+    #[test]
+    fn stale_symbol_appends_to_flags() {
+        let mut status = test_status("desc");
+        status.is_stale = true;
+        let plain = strip_ansi(&format_prompt(&status, &test_options(29)));
+        assert_eq!(plain, "* abcdefgh main CHS (empty) desc");
+    }
+
+    #[test]
+    fn push_symbol_appends_to_flags() {
+        let mut status = test_status("desc");
+        status.needs_push = true;
+        let plain = strip_ansi(&format_prompt(&status, &test_options(29)));
+        assert_eq!(plain, "* abcdefgh main CHP (empty) desc");
+    }
+
+    #[test]
+    fn dirty_symbol_follows_other_flags() {
+        let mut status = test_status("desc");
+        status.empty = false;
+        status.dirty = true;
+        let plain = strip_ansi(&format_prompt(&status, &test_options(29)));
+        assert_eq!(plain, "* abcdefgh main CH* desc");
+    }
+
+    #[test]
+    fn signed_symbol_requires_good_signature() {
+        let mut status = test_status("desc");
+        status.signed = true;
+        status.signature_status = SignatureStatus::Bad;
+        let plain = strip_ansi(&format_prompt(&status, &test_options(29)));
+        assert_eq!(plain, "* abcdefgh main CH (empty) desc");
+
+        status.signature_status = SignatureStatus::Good;
+        let plain = strip_ansi(&format_prompt(&status, &test_options(29)));
+        assert_eq!(plain, "* abcdefgh main CHV (empty) desc");
+    }
+
+    #[test]
+    fn conflict_symbol_includes_count() {
+        let mut status = test_status("desc");
+        status.conflict_count = 3;
+        status.conflicted_files = vec!["a".into(), "b".into(), "c".into()];
+        let plain = strip_ansi(&format_prompt(&status, &test_options(29)));
+        assert_eq!(plain, "* abcdefgh main C3H (empty) desc");
+    }
+
+    #[test]
+    fn divergent_symbol_includes_count() {
+        let mut status = test_status("desc");
+        status.divergent = true;
+        status.divergent_count = 3;
+        let plain = strip_ansi(&format_prompt(&status, &test_options(29)));
+        assert_eq!(plain, "* abcdefgh main CD3H (empty) desc");
+    }
+
+    #[test]
+    fn truncate_words_breaks_on_whitespace() {
+        let mut options = test_options(12);
+        options.truncate_words = true;
+
+        let words = strip_ansi(&format_prompt(&test_status("fix the parser bug"), &options));
+        assert!(words.ends_with(" fix the…"));
+
+        let boundary = strip_ansi(&format_prompt(&test_status("fix the pars bug"), &options));
+        assert!(boundary.ends_with(" fix the pars…"));
+
+        let single = strip_ansi(&format_prompt(
+            &test_status("refactoring-everything"),
+            &options,
+        ));
+        assert!(single.ends_with(" refactoring-…"));
+
+        options.truncate_words = false;
+        let chars = strip_ansi(&format_prompt(&test_status("fix the parser bug"), &options));
+        assert!(chars.ends_with(" fix the pars…"));
+    }
+
+    #[test]
+    fn max_bookmarks_adds_overflow_indicator() {
+        let mut status = test_status("desc");
+        for name in ["a", "b", "c"] {
+            status.bookmarks.push(Bookmark {
+                name: name.to_string(),
+                distance: 1,
+                target: "kkkkkkkk".to_string(),
+                is_tracked: false,
+                has_remote: false,
+                is_synced: true,
+                needs_push: false,
+            });
+        }
+        let mut options = test_options(29);
+
+        options.max_bookmarks = Some(2);
+        let plain = strip_ansi(&format_prompt(&status, &options));
+        assert_eq!(plain, "* abcdefgh main a +2 CH (empty) desc");
+
+        options.max_bookmarks = Some(0);
+        let plain = strip_ansi(&format_prompt(&status, &options));
+        assert_eq!(plain, "* abcdefgh +4 CH (empty) desc");
+
+        options.max_bookmarks = Some(10);
+        let plain = strip_ansi(&format_prompt(&status, &options));
+        assert_eq!(plain, "* abcdefgh main a b c CH (empty) desc");
+    }
+
+    #[test]
+    fn json_output_maps_segments_by_name() {
+        let mut status = test_status("desc");
+        status.bookmarks.clear();
+        let mut options = test_options(29);
+        options.no_color = true;
+        options.json = true;
+
+        let rendered = format_prompt(&status, &options);
+        let json: serde_json::Value = serde_json::from_str(&rendered).unwrap();
+        assert_eq!(json["icon"], "*");
+        assert_eq!(json["change_id"], "abcdefgh");
+        assert_eq!(json["bookmarks"], serde_json::Value::Null);
+        assert_eq!(json["flags"], "CH");
+        assert_eq!(json["empty"], "(empty)");
+        assert_eq!(json["description"], "desc");
+    }
+
+    #[test]
+    fn root_text_replaces_description() {
+        let mut status = test_status("");
+        status.is_root = true;
+        let plain = strip_ansi(&format_prompt(&status, &test_options(29)));
+        assert_eq!(plain, "* abcdefgh main CH (empty) (root)");
+
+        status.description = "initial import".to_string();
+        let plain = strip_ansi(&format_prompt(&status, &test_options(29)));
+        assert_eq!(plain, "* abcdefgh main CH (empty) initial import");
     }
 
     #[test]
@@ -518,5 +3397,26 @@ mod tests {
 
         let zero = strip_ansi(&format_prompt(&test_status("hello"), &test_options(0)));
         assert!(zero.ends_with("…"));
+
+        let mut dots = test_options(4);
+        dots.ellipsis = "...".to_string();
+        let custom = strip_ansi(&format_prompt(&test_status("hello"), &dots));
+        assert!(custom.ends_with("hell..."));
+
+        let mut none = test_options(4);
+        none.ellipsis = String::new();
+        let bare = strip_ansi(&format_prompt(&test_status("hello"), &none));
+        assert!(bare.ends_with(" hell"));
+
+        let mut zero_dots = test_options(0);
+        zero_dots.ellipsis = "...".to_string();
+        let zero_custom = strip_ansi(&format_prompt(&test_status("hello"), &zero_dots));
+        assert!(zero_custom.ends_with(" ..."));
+    }
+
+    #[test]
+    fn jj_lib_version_matches_the_pinned_dependency() {
+        let manifest = include_str!("../Cargo.toml");
+        assert!(manifest.contains(&format!("jj-lib = \"={JJ_LIB_VERSION}\"")));
     }
 }